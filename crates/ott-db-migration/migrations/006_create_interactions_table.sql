@@ -0,0 +1,24 @@
+-- Feed interactions sent by clients through app.bsky.feed.sendInteractions.
+-- The vector of the item is copied in on insert, the vectors table only
+-- keeps posts for a couple of hours
+
+CREATE TABLE interactions (
+    id BIGSERIAL PRIMARY KEY,
+    did VARCHAR NOT NULL,
+    item VARCHAR NOT NULL,
+    event VARCHAR NOT NULL,
+    feed_context VARCHAR,
+    vector vector,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX interactions_did_event_created_at ON interactions (did, event, created_at DESC);
+
+-- Interactions look up the vector of their item by uri
+CREATE INDEX vectors_uri ON vectors (uri);
+
+SELECT cron.schedule('interactions-retention', '0 * * * *',
+    $$DELETE FROM interactions WHERE created_at < NOW() - INTERVAL '30 days'$$);
+
+GRANT ALL PRIVILEGES ON TABLE public.interactions TO app;
+GRANT ALL PRIVILEGES ON ALL SEQUENCES IN SCHEMA public TO app;
@@ -0,0 +1,4 @@
+-- Like velocity of the post when ott-filter forwarded it, likes decayed by
+-- their age. Rows from before have no velocity to rank by
+
+ALTER TABLE vectors ADD COLUMN score DOUBLE PRECISION NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+-- Tombstones of deleted posts. A delete can overtake the embedding of its
+-- post, so inserts skip and feeds filter tombstoned uris. Kept a bit longer
+-- than the vectors
+
+CREATE TABLE deleted_posts (
+    uri VARCHAR PRIMARY KEY,
+    deleted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX deleted_posts_deleted_at ON deleted_posts (deleted_at);
+
+SELECT cron.schedule('deleted-posts-retention', '*/15 * * * *',
+    $$DELETE FROM deleted_posts WHERE deleted_at < NOW() - INTERVAL '3 hours'$$);
+
+GRANT ALL PRIVILEGES ON TABLE public.deleted_posts TO app;
@@ -0,0 +1,17 @@
+-- Approximate nearest neighbour search over the posts. HNSW needs the
+-- dimensions of the column, 384 for all-MiniLM-L6-v2 (ott-embed and
+-- ott-xrpc have to embed with it, or another model of that size, they check
+-- on start). Vectors of another length are not deleted here, see "Change the
+-- model" in the README for re-embedding them first
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM vectors WHERE vector_dims(vector) <> 384) THEN
+        RAISE EXCEPTION 'vectors holds vectors of another length than 384, remove or re-embed them first';
+    END IF;
+END
+$$;
+
+ALTER TABLE vectors ALTER COLUMN vector TYPE vector(384);
+
+CREATE INDEX vectors_vector_hnsw ON vectors USING hnsw (vector vector_cosine_ops);
@@ -0,0 +1,16 @@
+-- Query vectors of the feeds users are paging through, so that every page
+-- of a session is ranked against the vector of its first page, whichever
+-- replica serves it
+
+CREATE TABLE feed_sessions (
+    id BIGINT PRIMARY KEY,
+    did VARCHAR NOT NULL,
+    profile vector NOT NULL,
+    exclude VARCHAR[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+SELECT cron.schedule('feed-sessions-retention', '*/15 * * * *',
+    $$DELETE FROM feed_sessions WHERE created_at < NOW() - INTERVAL '2 hours'$$);
+
+GRANT ALL PRIVILEGES ON TABLE public.feed_sessions TO app;
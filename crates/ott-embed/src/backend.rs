@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+
+use crate::embedder::{Embedder, HashEmbedder};
+use crate::openai_client::OpenAiEmbedding;
+use crate::tei_client::TextEmbedding;
+
+const TEI_URL: &str = "http://tei-host-service:8080";
+
+/// What embeds the texts. Shared by ott-embed and ott-xrpc, their vectors
+/// are only comparable when both are configured alike
+#[derive(Debug, Clone, Args)]
+pub struct Backend {
+    #[arg(long, env = "EMBEDDER", value_enum, default_value_t = EmbedderKind::Tei)]
+    pub embedder: EmbedderKind,
+
+    #[arg(long, env = "TEI_URL", default_value = TEI_URL)]
+    pub tei_url: String,
+
+    /// Base URL of the `openai` embedder, up to and including `/v1`
+    #[arg(long, env = "OPENAI_URL", required_if_eq("embedder", "openai"))]
+    pub openai_url: Option<String>,
+
+    /// Model the `openai` embedder asks for
+    #[arg(long, env = "OPENAI_MODEL", required_if_eq("embedder", "openai"))]
+    pub openai_model: Option<String>,
+
+    /// Length of the vectors of the `openai` embedder, for models that can shorten them
+    #[arg(long, env = "OPENAI_DIMENSIONS")]
+    pub openai_dimensions: Option<u32>,
+
+    #[arg(long, env = "OPENAI_API_KEY", hide_env_values = true)]
+    pub openai_api_key: Option<String>,
+
+    /// Directory with the sentence transformer of the `cpu` embedder
+    #[arg(long, env = "MODEL_DIR", required_if_eq("embedder", "cpu"))]
+    pub model_dir: Option<PathBuf>,
+
+    /// Length of the vectors of the `hash` embedder
+    #[arg(long, env = "HASH_DIMENSIONS", default_value_t = 384)]
+    pub hash_dimensions: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum EmbedderKind {
+    /// A text-embeddings-inference server
+    Tei,
+    /// A server with an OpenAI compatible `/v1/embeddings` API
+    Openai,
+    /// A model run in-process, only offered by builds with the `cpu-model` feature
+    #[cfg_attr(not(feature = "cpu-model"), value(skip))]
+    Cpu,
+    /// Hashed words, deterministic but far from a model, for trying things out
+    Hash,
+}
+
+impl Backend {
+    /// The embedder configured, an error when it is missing its settings or
+    /// not built in
+    pub fn embedder(&self) -> Result<Arc<dyn Embedder>> {
+        Ok(match self.embedder {
+            EmbedderKind::Tei => Arc::new(TextEmbedding::new(&self.tei_url)),
+            EmbedderKind::Openai => Arc::new(self.openai()?),
+            EmbedderKind::Cpu => self.cpu_model()?,
+            EmbedderKind::Hash => Arc::new(HashEmbedder::new(self.hash_dimensions)),
+        })
+    }
+
+    fn openai(&self) -> Result<OpenAiEmbedding> {
+        let url = self
+            .openai_url
+            .as_ref()
+            .ok_or_else(|| anyhow!("The openai embedder needs OPENAI_URL"))?;
+        let model = self
+            .openai_model
+            .as_ref()
+            .ok_or_else(|| anyhow!("The openai embedder needs OPENAI_MODEL"))?;
+        let mut client = OpenAiEmbedding::new(url, model);
+        if let Some(dimensions) = self.openai_dimensions {
+            client = client.with_dimensions(dimensions);
+        }
+        if let Some(api_key) = &self.openai_api_key {
+            client = client.with_api_key(api_key);
+        }
+        Ok(client)
+    }
+
+    #[cfg(feature = "cpu-model")]
+    fn cpu_model(&self) -> Result<Arc<dyn Embedder>> {
+        let dir = self
+            .model_dir
+            .as_ref()
+            .ok_or_else(|| anyhow!("The cpu embedder needs MODEL_DIR"))?;
+        let model = crate::cpu_model::CpuModel::load(dir)
+            .map_err(|e| anyhow!("Failed to load the model in {}: {}", dir.display(), e))?;
+        Ok(Arc::new(model))
+    }
+
+    #[cfg(not(feature = "cpu-model"))]
+    fn cpu_model(&self) -> Result<Arc<dyn Embedder>> {
+        Err(anyhow!(
+            "The cpu embedder needs a build with the cpu-model feature"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use rstest::rstest;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        backend: Backend,
+    }
+
+    fn parse(args: &[&str]) -> Result<Backend, clap::Error> {
+        TestCli::try_parse_from(std::iter::once("test").chain(args.iter().copied()))
+            .map(|cli| cli.backend)
+    }
+
+    #[rstest]
+    fn tei_by_default() {
+        let backend = parse(&[]).unwrap();
+        assert_eq!(backend.embedder, EmbedderKind::Tei);
+        assert_eq!(backend.tei_url, TEI_URL);
+    }
+
+    #[rstest]
+    fn openai_needs_url_and_model() {
+        let error = parse(&["--embedder", "openai"]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+        let backend = parse(&[
+            "--embedder",
+            "openai",
+            "--openai-url",
+            "http://vllm:8000/v1",
+            "--openai-model",
+            "all-minilm",
+        ])
+        .unwrap();
+        assert_eq!(backend.embedder, EmbedderKind::Openai);
+        assert!(backend.embedder().is_ok());
+    }
+
+    #[cfg(not(feature = "cpu-model"))]
+    #[rstest]
+    fn cpu_is_rejected_without_the_feature() {
+        let error = parse(&["--embedder", "cpu", "--model-dir", "/models"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::InvalidValue);
+    }
+
+    #[cfg(not(feature = "cpu-model"))]
+    #[rstest]
+    fn cpu_fails_without_the_feature() {
+        let backend = Backend {
+            embedder: EmbedderKind::Cpu,
+            model_dir: Some(PathBuf::from("/models")),
+            ..parse(&[]).unwrap()
+        };
+        assert!(backend.embedder().is_err());
+    }
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Collects items into batches of at most `size`, none waiting longer than
+/// `latency` for its batch to fill up
+pub struct MicroBatcher<T> {
+    items: Vec<T>,
+    size: usize,
+    latency: Duration,
+    /// When the items collected so far are due, set by the first of them
+    deadline: Option<Instant>,
+}
+
+impl<T> MicroBatcher<T> {
+    pub fn new(size: usize, latency: Duration) -> Self {
+        Self {
+            items: Vec::with_capacity(size),
+            size: size.max(1),
+            latency,
+            deadline: None,
+        }
+    }
+
+    /// Add an item, the batch once it is full
+    pub fn push(&mut self, item: T) -> Option<Vec<T>> {
+        if self.items.is_empty() {
+            self.deadline = Some(Instant::now() + self.latency);
+        }
+        self.items.push(item);
+        if self.items.len() >= self.size {
+            self.take()
+        } else {
+            None
+        }
+    }
+
+    /// The items collected so far, none when there are none
+    pub fn take(&mut self) -> Option<Vec<T>> {
+        self.deadline = None;
+        if self.items.is_empty() {
+            None
+        } else {
+            Some(std::mem::replace(
+                &mut self.items,
+                Vec::with_capacity(self.size),
+            ))
+        }
+    }
+
+    /// When the items collected so far should be taken, even if not full
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[tokio::test(start_paused = true)]
+    async fn full_batches_are_returned() {
+        let mut batcher = MicroBatcher::new(3, Duration::from_millis(20));
+        assert_eq!(batcher.push(1), None);
+        assert_eq!(batcher.push(2), None);
+        assert_eq!(batcher.push(3), Some(vec![1, 2, 3]));
+        assert_eq!(batcher.deadline(), None);
+        assert_eq!(batcher.take(), None);
+    }
+
+    #[rstest]
+    #[tokio::test(start_paused = true)]
+    async fn deadline_is_set_by_the_first_item() {
+        let mut batcher = MicroBatcher::new(3, Duration::from_millis(20));
+        let start = Instant::now();
+        batcher.push(1);
+        tokio::time::advance(Duration::from_millis(15)).await;
+        batcher.push(2);
+        assert_eq!(batcher.deadline(), Some(start + Duration::from_millis(20)));
+        assert_eq!(batcher.take(), Some(vec![1, 2]));
+        assert_eq!(batcher.deadline(), None);
+    }
+}
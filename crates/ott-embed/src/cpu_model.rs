@@ -0,0 +1,209 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config};
+use futures::future::BoxFuture;
+use tokenizers::{PaddingParams, PaddingStrategy, Tokenizer, TruncationParams};
+
+use crate::embedder::{Embedder, EmbedderError};
+
+/// A BERT sentence transformer run in-process on CPU, mean pooled and
+/// normalized like TEI does for models such as `all-MiniLM-L6-v2`
+pub struct CpuModel {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl CpuModel {
+    /// Loads `config.json`, `tokenizer.json` and `model.safetensors` from
+    /// `dir`, as downloaded from the model's page on the hugging face hub
+    pub fn load(dir: &Path) -> Result<Self, EmbedderError> {
+        let device = Device::Cpu;
+        let config = std::fs::read_to_string(dir.join("config.json")).map_err(model_error)?;
+        let config: Config = serde_json::from_str(&config).map_err(model_error)?;
+
+        let mut tokenizer =
+            Tokenizer::from_file(dir.join("tokenizer.json")).map_err(model_error)?;
+        tokenizer.with_padding(Some(PaddingParams {
+            strategy: PaddingStrategy::BatchLongest,
+            ..Default::default()
+        }));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(model_error)?;
+
+        // Safety: the weights are mapped read only and not modified while loaded
+        let weights = unsafe {
+            VarBuilder::from_mmaped_safetensors(
+                &[dir.join("model.safetensors")],
+                DType::F32,
+                &device,
+            )
+        }
+        .map_err(model_error)?;
+        let model = BertModel::load(weights, &config).map_err(model_error)?;
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                model,
+                tokenizer,
+                device,
+            }),
+        })
+    }
+}
+
+impl Inner {
+    fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, EmbedderError> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts, true)
+            .map_err(model_error)?;
+        let tensor = |rows: Vec<&[u32]>| -> candle_core::Result<Tensor> {
+            let rows = rows
+                .into_iter()
+                .map(|row| Tensor::new(row, &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Tensor::stack(&rows, 0)
+        };
+        let ids = tensor(encodings.iter().map(|e| e.get_ids()).collect()).map_err(model_error)?;
+        let mask = tensor(encodings.iter().map(|e| e.get_attention_mask()).collect())
+            .map_err(model_error)?;
+        self.pool(&ids, &mask).map_err(model_error)
+    }
+
+    /// Mean of the token embeddings of each text, padding left out, scaled to unit length
+    fn pool(&self, ids: &Tensor, mask: &Tensor) -> candle_core::Result<Vec<Vec<f32>>> {
+        let tokens = self.model.forward(ids, &ids.zeros_like()?, Some(mask))?;
+        let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+        let mean = tokens
+            .broadcast_mul(&mask)?
+            .sum(1)?
+            .broadcast_div(&mask.sum(1)?)?;
+        let norm = mean.sqr()?.sum_keepdim(1)?.sqrt()?;
+        mean.broadcast_div(&norm)?.to_vec2()
+    }
+}
+
+impl Embedder for CpuModel {
+    fn embed_batch<'a>(
+        &'a self,
+        texts: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f32>>, EmbedderError>> {
+        let inner = self.inner.clone();
+        let texts: Vec<String> = texts.iter().map(|text| text.to_string()).collect();
+        // Inference keeps the thread busy, off the runtime's workers
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || inner.embed(texts))
+                .await
+                .map_err(model_error)?
+        })
+    }
+}
+
+fn model_error(e: impl std::fmt::Display) -> EmbedderError {
+    EmbedderError::Model(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_nn::VarMap;
+    use rstest::{fixture, rstest};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    /// A BERT with random weights and a whitespace tokenizer, small enough to write in a test
+    #[fixture]
+    fn model_dir() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let config = json!({
+            "vocab_size": 8,
+            "hidden_size": 16,
+            "num_hidden_layers": 2,
+            "num_attention_heads": 2,
+            "intermediate_size": 32,
+            "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.0,
+            "max_position_embeddings": 32,
+            "type_vocab_size": 2,
+            "initializer_range": 0.02,
+            "layer_norm_eps": 1e-12,
+            "pad_token_id": 0,
+            "model_type": "bert"
+        });
+        std::fs::write(dir.path().join("config.json"), config.to_string()).unwrap();
+        let tokenizer = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "WhitespaceSplit" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": { "[PAD]": 0, "[UNK]": 1, "hello": 2, "world": 3, "cats": 4 },
+                "unk_token": "[UNK]"
+            }
+        });
+        std::fs::write(dir.path().join("tokenizer.json"), tokenizer.to_string()).unwrap();
+
+        let config: Config = serde_json::from_value(config).unwrap();
+        let weights = VarMap::new();
+        BertModel::load(
+            VarBuilder::from_varmap(&weights, DType::F32, &Device::Cpu),
+            &config,
+        )
+        .unwrap();
+        weights.save(dir.path().join("model.safetensors")).unwrap();
+        dir
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn embeddings_are_normalized(model_dir: TempDir) {
+        let model = CpuModel::load(model_dir.path()).unwrap();
+        let vectors = model.embed_batch(&["hello world", "cats"]).await.unwrap();
+        assert_eq!(vectors.len(), 2);
+        for vector in vectors {
+            assert_eq!(vector.len(), 16);
+            let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-4, "{}", norm);
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn padding_is_left_out(model_dir: TempDir) {
+        let model = CpuModel::load(model_dir.path()).unwrap();
+        let alone = model.embed_batch(&["hello"]).await.unwrap();
+        let padded = model
+            .embed_batch(&["hello", "hello world cats cats"])
+            .await
+            .unwrap();
+        for (a, b) in alone[0].iter().zip(&padded[0]) {
+            assert!((a - b).abs() < 1e-4, "{} {}", a, b);
+        }
+    }
+
+    #[rstest]
+    fn missing_model_fails_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            CpuModel::load(dir.path()),
+            Err(EmbedderError::Model(_))
+        ));
+    }
+}
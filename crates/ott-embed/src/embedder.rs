@@ -0,0 +1,164 @@
+use std::fmt;
+
+use futures::future::BoxFuture;
+use tokio::time::Instant;
+
+use crate::http_retry::HttpError;
+use crate::openai_client::OpenAiEmbedding;
+use crate::tei_client::TextEmbedding;
+
+#[derive(Debug)]
+pub enum EmbedderError {
+    /// The embedding server, TEI or OpenAI compatible, failed
+    Http(HttpError),
+    /// The in-process model failed to load or run
+    Model(String),
+}
+
+impl EmbedderError {
+    /// Errors that go away once the backend is up again, worth retrying
+    pub fn is_transient(&self) -> bool {
+        match self {
+            EmbedderError::Http(e) => e.is_transient(),
+            EmbedderError::Model(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for EmbedderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbedderError::Http(e) => write!(f, "{}", e),
+            EmbedderError::Model(e) => write!(f, "model failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EmbedderError {}
+
+impl From<HttpError> for EmbedderError {
+    fn from(e: HttpError) -> Self {
+        EmbedderError::Http(e)
+    }
+}
+
+/// Turns post texts into vectors. The vectors are compared with the ones
+/// of the likes ott-xrpc embeds, so both have to use the same backend
+pub trait Embedder: Send + Sync {
+    /// Embeddings of the texts, in their order
+    fn embed_batch<'a>(
+        &'a self,
+        texts: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f32>>, EmbedderError>>;
+
+    /// When the backend is known to be down, callers hold off until then
+    fn paused_until(&self) -> Option<Instant> {
+        None
+    }
+}
+
+impl Embedder for TextEmbedding {
+    fn embed_batch<'a>(
+        &'a self,
+        texts: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f32>>, EmbedderError>> {
+        Box::pin(async move { Ok(TextEmbedding::embed_batch(self, texts).await?) })
+    }
+
+    fn paused_until(&self) -> Option<Instant> {
+        self.breaker().open_until()
+    }
+}
+
+impl Embedder for OpenAiEmbedding {
+    fn embed_batch<'a>(
+        &'a self,
+        texts: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f32>>, EmbedderError>> {
+        Box::pin(async move { Ok(OpenAiEmbedding::embed_batch(self, texts).await?) })
+    }
+
+    fn paused_until(&self) -> Option<Instant> {
+        self.breaker().open_until()
+    }
+}
+
+/// Deterministic embeddings of the hashed words of a text, texts sharing
+/// words end up close. Meant for tests and trying things out without a model
+pub struct HashEmbedder {
+    dimensions: usize,
+}
+
+impl HashEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        Self {
+            dimensions: dimensions.max(1),
+        }
+    }
+
+    pub fn embed(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimensions];
+        for word in text.split_whitespace() {
+            let hash = fnv1a(word.to_lowercase().as_bytes());
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % self.dimensions as u64) as usize] += sign;
+        }
+        let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|x| *x /= norm);
+        }
+        vector
+    }
+}
+
+impl Embedder for HashEmbedder {
+    fn embed_batch<'a>(
+        &'a self,
+        texts: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<Vec<Vec<f32>>, EmbedderError>> {
+        Box::pin(async move { Ok(texts.iter().map(|text| self.embed(text)).collect()) })
+    }
+}
+
+/// Stable across builds and platforms, unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| a * b).sum()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn hash_embeddings_are_deterministic() {
+        let embedder = HashEmbedder::new(384);
+        let texts = ["The cat sat on the mat", "the CAT sat on the mat"];
+        let vectors = embedder.embed_batch(&texts).await.unwrap();
+        assert_eq!(vectors[0].len(), 384);
+        assert_eq!(vectors[0], vectors[1]);
+        assert_eq!(vectors[0], HashEmbedder::new(384).embed(texts[0]));
+        assert!((cosine(&vectors[0], &vectors[0]) - 1.0).abs() < 1e-5);
+    }
+
+    #[rstest]
+    fn shared_words_are_closer() {
+        let embedder = HashEmbedder::new(384);
+        let cats = embedder.embed("cats are great pets");
+        let more_cats = embedder.embed("cats are lazy pets");
+        let rust = embedder.embed("rust compiles to fast binaries");
+        assert!(cosine(&cats, &more_cats) > cosine(&cats, &rust));
+    }
+
+    #[rstest]
+    fn empty_text_is_zero() {
+        assert!(HashEmbedder::new(8).embed("").iter().all(|x| *x == 0.0));
+    }
+}
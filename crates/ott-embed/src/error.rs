@@ -0,0 +1,76 @@
+use std::fmt;
+
+use ott_types::{DeletedPost, Post};
+use serde::de::DeserializeOwned;
+
+use crate::embedder::EmbedderError;
+
+#[derive(Debug)]
+pub enum EmbedError {
+    /// Not shaped like the posts and deletes ott-filter publishes
+    Malformed(serde_json::Error),
+    /// The embedder failed to embed a post
+    Embedding(EmbedderError),
+    /// Postgres failed to store a batch of embeddings
+    Store(sqlx::Error),
+    /// A task of the pipeline stopped, nothing sent to it is handled anymore
+    Stopped(&'static str),
+}
+
+impl EmbedError {
+    /// Kind of failure, the reason of its dead letters. `rejected` posts
+    /// were refused by the embedder and fail again when replayed
+    pub fn reason(&self) -> &'static str {
+        match self {
+            EmbedError::Malformed(_) => "malformed",
+            EmbedError::Embedding(e) if e.is_transient() => "embedding",
+            EmbedError::Embedding(_) => "rejected",
+            EmbedError::Store(_) => "store",
+            EmbedError::Stopped(_) => "stopped",
+        }
+    }
+}
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbedError::Malformed(e) => write!(f, "malformed record: {}", e),
+            EmbedError::Embedding(e) => write!(f, "failed to embed: {}", e),
+            EmbedError::Store(e) => write!(f, "failed to store: {}", e),
+            EmbedError::Stopped(task) => write!(f, "{} task stopped", task),
+        }
+    }
+}
+
+impl std::error::Error for EmbedError {}
+
+fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T, EmbedError> {
+    serde_json::from_slice(value).map_err(EmbedError::Malformed)
+}
+
+pub fn decode_post(value: &[u8]) -> Result<Post, EmbedError> {
+    decode(value)
+}
+
+pub fn decode_delete(value: &[u8]) -> Result<DeletedPost, EmbedError> {
+    decode(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(b"{\"uri\":\"at://a\"}")]
+    #[case(b"\xff\xfe")]
+    fn malformed_posts(#[case] value: &[u8]) {
+        assert!(matches!(decode_post(value), Err(EmbedError::Malformed(_))));
+    }
+
+    #[rstest]
+    fn decodes_deletes() {
+        let deleted = decode_delete(br#"{"uri":"at://a"}"#).unwrap();
+        assert_eq!(deleted.uri, "at://a");
+    }
+}
@@ -0,0 +1,206 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::{debug, warn};
+
+/// Failure of an embedding server spoken to over HTTP, TEI or OpenAI
+/// compatible
+#[derive(Debug)]
+pub enum HttpError {
+    /// The server could not be reached or did not answer in time
+    Transport(reqwest::Error),
+    /// The server rejected the request
+    Http(StatusCode),
+    /// The response is not one embedding per input
+    Decode(String),
+    /// 429 or 503, the server's queue is full or the model is still loading
+    Overloaded(StatusCode),
+}
+
+impl HttpError {
+    /// Errors that go away once the server is up again, worth retrying
+    pub fn is_transient(&self) -> bool {
+        matches!(self, HttpError::Transport(_) | HttpError::Overloaded(_))
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Transport(e) => write!(f, "embedding server unreachable: {}", e),
+            HttpError::Http(status) => write!(f, "embedding server answered {}", status),
+            HttpError::Decode(e) => write!(f, "invalid embedding response: {}", e),
+            HttpError::Overloaded(status) => write!(f, "embedding server overloaded: {}", status),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// The error of a response that is not a success
+pub(crate) fn check_status(status: StatusCode) -> Result<(), HttpError> {
+    match status {
+        status if status.is_success() => Ok(()),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+            Err(HttpError::Overloaded(status))
+        }
+        status => Err(HttpError::Http(status)),
+    }
+}
+
+/// How often transient errors are retried, waiting a random time of up to
+/// `base` doubled on every attempt, at most `max`
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    pub attempts: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Retry {
+    /// The wait after the failed attempt `attempt`, counted from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max);
+        ceiling.mul_f64(rand::rng().random_range(0.0..=1.0))
+    }
+
+    /// Send requests until one succeeds, fails for good or `attempts` failed
+    pub(crate) async fn run<T, F, Fut>(
+        &self,
+        breaker: &CircuitBreaker,
+        mut request: F,
+    ) -> Result<T, HttpError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, HttpError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match request().await {
+                Ok(response) => {
+                    breaker.succeeded();
+                    return Ok(response);
+                }
+                Err(e) if e.is_transient() => {
+                    breaker.failed();
+                    if attempt >= self.attempts {
+                        return Err(e);
+                    }
+                    debug!("Attempt {} failed: {}", attempt, e);
+                }
+                Err(e) => return Err(e),
+            }
+            sleep(self.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            base: Duration::from_millis(200),
+            max: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Opens after `threshold` failed requests in a row so callers can stop
+/// sending while the server is down, closes again after `cooldown` to let the next
+/// request probe whether it is back
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// When requests may be sent again, none when they may be now
+    pub fn open_until(&self) -> Option<Instant> {
+        let state = self.state.lock().unwrap();
+        state.open_until.filter(|until| *until > Instant::now())
+    }
+
+    pub(crate) fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.failures >= self.threshold {
+            warn!("Embedding server is back, closing the circuit");
+        }
+        *state = BreakerState::default();
+    }
+
+    pub(crate) fn failed(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if state.failures >= self.threshold {
+            if state.failures == self.threshold {
+                warn!(
+                    "Embedding server failed {} times in a row, opening the circuit",
+                    state.failures
+                );
+            }
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(10))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(StatusCode::OK, None)]
+    #[case(StatusCode::TOO_MANY_REQUESTS, Some(true))]
+    #[case(StatusCode::SERVICE_UNAVAILABLE, Some(true))]
+    #[case(StatusCode::PAYLOAD_TOO_LARGE, Some(false))]
+    #[case(StatusCode::UNAUTHORIZED, Some(false))]
+    fn statuses(#[case] status: StatusCode, #[case] transient: Option<bool>) {
+        assert_eq!(
+            check_status(status).err().map(|e| e.is_transient()),
+            transient
+        );
+    }
+
+    #[rstest]
+    fn backoff_grows_up_to_max() {
+        let retry = Retry {
+            attempts: 10,
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+        };
+        for attempt in 1..10 {
+            let ceiling = Duration::from_millis(100 << (attempt - 1)).min(retry.max);
+            assert!(retry.backoff(attempt) <= ceiling);
+        }
+    }
+}
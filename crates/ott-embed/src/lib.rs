@@ -1,2 +1,11 @@
+pub mod backend;
+pub mod batch;
+#[cfg(feature = "cpu-model")]
+pub mod cpu_model;
+pub mod embedder;
+pub mod error;
+pub mod http_retry;
+pub mod openai_client;
 pub mod pg_client;
+pub mod replay;
 pub mod tei_client;
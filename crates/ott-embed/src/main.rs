@@ -1,22 +1,104 @@
+use std::sync::Arc;
 use std::time::Duration;
 
+use clap::{builder::RangedU64ValueParser, Args, Parser, Subcommand};
+use futures::stream::{FuturesOrdered, StreamExt};
+use ott_embed::backend::Backend;
+use ott_embed::batch::MicroBatcher;
+use ott_embed::embedder::Embedder;
+use ott_embed::error::{decode_delete, decode_post, EmbedError};
 use ott_embed::pg_client::PgClient;
-use ott_embed::tei_client::TextEmbedding;
+use ott_embed::replay::{dead_letter, replay};
 use tokio::{
-    sync::mpsc::{Receiver, Sender},
-    time::interval,
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
+    task::JoinSet,
+    time::{interval, interval_at, sleep_until, Instant},
 };
 
-use tokio_stream::StreamExt;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
-use fluvio::{consumer::ConsumerConfigExtBuilder, Fluvio, Offset};
+use fluvio::Fluvio;
+use ott_stream::{ensure_topic, partition_count, ConsumerOptions, DeadLetters, TopicConsumer};
 use ott_types::{Embedding, Post};
 
-const TEI_URL: &str = "http://tei-host-service:8080";
 const TOPIC: &str = "posts";
-const PARTITION: u32 = 0;
+const DELETES_TOPIC: &str = "post-deletes";
+/// Records that could not be handled, with the reason
+const DLQ_TOPIC: &str = "embed-dlq";
+const CONSUMER_GROUP: &str = "ott-embed";
+/// Group of the replays, so each replay continues after the last one
+const REPLAY_GROUP: &str = "ott-embed-replay";
+
+/// How often the offset of the stored posts is committed
+const COMMIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the time spent per insert path is logged
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Failed inserts before a batch is dead-lettered
+const STORE_ATTEMPTS: u32 = 3;
+
+#[derive(Parser)]
+struct Cli {
+    #[command(flatten)]
+    backend: Backend,
+
+    #[command(flatten)]
+    batching: Batching,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// How posts are sent to the embedder, in batches of several requests at once
+#[derive(Args, Clone, Copy)]
+struct Batching {
+    /// Most posts embedded per request, at most TEI's `--max-client-batch-size`
+    #[arg(long, env = "EMBED_BATCH_SIZE", default_value_t = 32, value_parser = at_least_one())]
+    batch_size: usize,
+
+    /// Longest a post waits for its batch to fill up, in milliseconds
+    #[arg(long, env = "EMBED_BATCH_LATENCY_MS", default_value_t = 50)]
+    batch_latency_ms: u64,
+
+    /// Most requests to the embedder at once
+    #[arg(long, env = "EMBED_CONCURRENCY", default_value_t = 4, value_parser = at_least_one())]
+    concurrency: usize,
+}
+
+/// Nothing is ever embedded with no posts per request or no requests at once
+fn at_least_one() -> RangedU64ValueParser<usize> {
+    RangedU64ValueParser::new().range(1..)
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Publish the dead letters again to the topics they were consumed
+    /// from, once the embedder or postgres are back
+    Replay {
+        /// Only the letters failed for this reason, repeatable. Posts the
+        /// embedder `rejected` are not replayed unless asked for
+        #[arg(long = "reason", default_values = ["embedding", "store"])]
+        reasons: Vec<String>,
+        /// Skip the letters of posts failed this many times
+        #[arg(long, default_value_t = 5)]
+        max_attempts: u32,
+        /// Stop once no letter arrived for this many seconds
+        #[arg(long, default_value_t = 5)]
+        idle_secs: u64,
+    },
+}
+
+/// Passed down the pipeline, a checkpoint is acknowledged by the store task
+/// once everything sent before it is committed to pg
+enum Work<T> {
+    Item(T),
+    Checkpoint(oneshot::Sender<()>),
+}
 
 #[tokio::main]
 async fn main() {
@@ -25,105 +107,460 @@ async fn main() {
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let (embed_tx, embed_rx) = tokio::sync::mpsc::channel::<Post>(1000);
-    let (store_tx, store_rx) = tokio::sync::mpsc::channel::<Embedding>(1000);
+    let cli = Cli::parse();
+    if let Some(Command::Replay {
+        reasons,
+        max_attempts,
+        idle_secs,
+    }) = cli.command
+    {
+        let options = ConsumerOptions::from_env(REPLAY_GROUP).expect("Invalid consumer options");
+        let fluvio = Fluvio::connect()
+            .await
+            .expect("Failed to connect to Fluvio");
+        let idle = Duration::from_secs(idle_secs);
+        if let Err(e) = replay(&fluvio, DLQ_TOPIC, &options, &reasons, max_attempts, idle).await {
+            error!("Replay failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let read_task = tokio::spawn(async { read_task(embed_tx).await });
-    let embed_task = tokio::spawn(async { embed_task(embed_rx, store_tx).await });
-    let store_task = tokio::spawn(async { store_task(store_rx).await });
+    let options = ConsumerOptions::from_env(CONSUMER_GROUP).expect("Invalid consumer options");
+    let delete_options = options.clone();
 
-    let _result = tokio::join!(read_task, embed_task, store_task);
+    let fluvio = Fluvio::connect()
+        .await
+        .expect("Failed to connect to Fluvio");
+    let dead_letters = Arc::new(
+        DeadLetters::connect(&fluvio, DLQ_TOPIC)
+            .await
+            .expect("Failed to create dead letter producer"),
+    );
+
+    let embedder = match cli.backend.embedder() {
+        Ok(embedder) => embedder,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let pg_client = PgClient::new().await.expect("Failed to connect to db");
+    if let Err(e) = pg_client.check_embedder(embedder.as_ref()).await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    let (embed_tx, embed_rx) = tokio::sync::mpsc::channel::<Work<Post>>(1000);
+    let (store_tx, store_rx) = tokio::sync::mpsc::channel::<Work<(Post, Embedding)>>(1000);
+
+    // The process fails with any of its tasks, a stopped task stalls the others
+    let mut tasks = JoinSet::new();
+    tasks.spawn({
+        let dead_letters = dead_letters.clone();
+        async move { read_task(embed_tx, options, dead_letters).await }
+    });
+    tasks.spawn({
+        let dead_letters = dead_letters.clone();
+        async move { embed_task(embed_rx, store_tx, dead_letters, embedder, cli.batching).await }
+    });
+    tasks.spawn({
+        let dead_letters = dead_letters.clone();
+        async move {
+            store_task(store_rx, dead_letters).await;
+            Ok(())
+        }
+    });
+    tasks.spawn(async move {
+        delete_task(delete_options, dead_letters).await;
+        Ok(())
+    });
+
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error!("Task failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
 
-async fn read_task(sink: Sender<Post>) {
+async fn read_task(
+    sink: Sender<Work<Post>>,
+    options: ConsumerOptions,
+    dead_letters: Arc<DeadLetters>,
+) -> Result<(), EmbedError> {
     let fluvio = Fluvio::connect()
         .await
         .expect("Failed to connect to Fluvio");
+    let mut consumer = all_partitions(&fluvio, TOPIC, &options).await;
 
-    let config = ConsumerConfigExtBuilder::default()
-        .topic(TOPIC)
-        .partition(PARTITION)
-        .offset_start(Offset::beginning())
-        .build()
-        .expect("Failed to build consumer config");
-    let mut stream = fluvio
-        .consumer_with_config(config)
+    warn!("Ready to start consuming posts");
+    let mut commit_timer = interval(COMMIT_INTERVAL);
+    let mut uncommitted = false;
+    loop {
+        tokio::select! {
+            message = consumer.next() => {
+                let Some(Ok(record)) = message else {
+                    break;
+                };
+                match decode_post(record.value()) {
+                    Ok(post) => sink
+                        .send(Work::Item(post))
+                        .await
+                        .map_err(|_| EmbedError::Stopped("embed"))?,
+                    Err(e) => {
+                        warn!("Skipping post at {}: {}", record.offset(), e);
+                        dead_letters
+                            .send_record(TOPIC, &record, e.reason(), &e)
+                            .await;
+                    }
+                }
+                uncommitted = true;
+            }
+
+            _ = commit_timer.tick(), if uncommitted => {
+                // No reading until the posts sent so far are stored or
+                // dead-lettered, the commit covers all of them
+                let (done_tx, done_rx) = oneshot::channel();
+                sink.send(Work::Checkpoint(done_tx))
+                    .await
+                    .map_err(|_| EmbedError::Stopped("embed"))?;
+                if done_rx.await.is_ok() {
+                    match consumer.commit().await {
+                        Ok(()) => uncommitted = false,
+                        Err(e) => error!("{}", e),
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn all_partitions(fluvio: &Fluvio, topic: &str, options: &ConsumerOptions) -> TopicConsumer {
+    let count = partition_count(fluvio, topic)
         .await
-        .expect("Failed to create consumer");
+        .expect("Failed to get partitions");
+    let partitions: Vec<u32> = (0..count).collect();
+    TopicConsumer::connect(fluvio, topic, &partitions, options)
+        .await
+        .expect("Failed to create consumer")
+}
 
-    warn!("Ready to start consuming posts");
-    while let Some(message) = stream.next().await
-       && let Ok(record) = message
-    {
-        let post: Post = serde_json::from_slice(record.value()).expect("Invalid post message");
-        sink.send(post).await.expect("Failed to internally send post");
+async fn delete_task(options: ConsumerOptions, dead_letters: Arc<DeadLetters>) {
+    let fluvio = Fluvio::connect()
+        .await
+        .expect("Failed to connect to Fluvio");
+    // Published by ott-filter, which may not have started yet
+    ensure_topic(&fluvio, DELETES_TOPIC)
+        .await
+        .expect("Failed to create deletes topic");
+    let mut consumer = all_partitions(&fluvio, DELETES_TOPIC, &options).await;
+    let pg_client = PgClient::new().await.expect("Failed to connect to db");
+
+    warn!("Ready to start deleting posts");
+    let batch_size = 100;
+    let mut flush_timer = interval(Duration::from_millis(500));
+    let mut batch: Vec<String> = Vec::with_capacity(batch_size);
+    loop {
+        tokio::select! {
+            message = consumer.next(), if batch.len() < batch_size => {
+                let Some(Ok(record)) = message else {
+                    break;
+                };
+                match decode_delete(record.value()) {
+                    Ok(deleted) => batch.push(deleted.uri),
+                    Err(e) => {
+                        warn!("Skipping delete at {}: {}", record.offset(), e);
+                        dead_letters
+                            .send_record(DELETES_TOPIC, &record, e.reason(), &e)
+                            .await;
+                    }
+                }
+                if batch.len() >= batch_size {
+                    delete_batch(&pg_client, &dead_letters, &mut consumer, &mut batch).await;
+                    flush_timer.reset();
+                }
+            }
+
+            _ = flush_timer.tick() => {
+                if !batch.is_empty() {
+                    delete_batch(&pg_client, &dead_letters, &mut consumer, &mut batch).await;
+                }
+            }
+        }
     }
 }
 
-async fn embed_task(mut posts: Receiver<Post>, sink: Sender<Embedding>) {
-    let tei_client = TextEmbedding::new(TEI_URL);
+/// Delete the batch and commit its offsets, kept for the next try on errors
+async fn delete_batch(
+    pg_client: &PgClient,
+    dead_letters: &DeadLetters,
+    consumer: &mut TopicConsumer,
+    batch: &mut Vec<String>,
+) {
+    if let Err(e) = pg_client.delete_posts(batch).await {
+        error!("Delete error: {}", e);
+        return;
+    }
+    batch.clear();
+    if let Err(e) = dead_letters.flush().await {
+        error!("{}", e);
+        return;
+    }
+    if let Err(e) = consumer.commit().await {
+        error!("{}", e);
+    }
+}
+
+async fn embed_task(
+    mut posts: Receiver<Work<Post>>,
+    sink: Sender<Work<(Post, Embedding)>>,
+    dead_letters: Arc<DeadLetters>,
+    embedder: Arc<dyn Embedder>,
+    batching: Batching,
+) -> Result<(), EmbedError> {
+    let mut batcher = MicroBatcher::new(
+        batching.batch_size,
+        Duration::from_millis(batching.batch_latency_ms),
+    );
+    // Batches being embedded, their embeddings are stored in the order read
+    let mut in_flight = FuturesOrdered::new();
 
     warn!("Ready to start embedding posts");
-    while let Some(post) = posts.recv().await {
-        let embedding = tei_client.embed(&post.text).await;
-        match embedding {
-            Ok(vec) => {
-                sink.send(Embedding {
-                    uri: post.uri,
-                    vector: vec,
-                })
-                .await
-                .expect("Failed to send embedding between tasks");
+    loop {
+        // Nothing more is read from the posts while the embedder is down,
+        // which pauses the consumer once the channel is full
+        let paused_until = embedder.paused_until();
+        let ready = in_flight.len() < batching.concurrency && paused_until.is_none();
+        let deadline = batcher.deadline();
+        tokio::select! {
+            work = posts.recv(), if ready => {
+                match work {
+                    Some(Work::Item(post)) => {
+                        if let Some(batch) = batcher.push(post) {
+                            in_flight.push_back(embed(embedder.clone(), batch));
+                        }
+                    }
+                    Some(Work::Checkpoint(done)) => {
+                        // Everything before the checkpoint reaches the store task first
+                        if let Some(batch) = batcher.take() {
+                            in_flight.push_back(embed(embedder.clone(), batch));
+                        }
+                        while let Some(embedded) = in_flight.next().await {
+                            forward(&sink, &dead_letters, embedded).await?;
+                        }
+                        sink.send(Work::Checkpoint(done))
+                            .await
+                            .map_err(|_| EmbedError::Stopped("store"))?;
+                    }
+                    None => break,
+                }
             }
-            Err(e) => {
-                error!("Failed to embed post! {} {}", post.uri, e);
+
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if ready && deadline.is_some() => {
+                if let Some(batch) = batcher.take() {
+                    in_flight.push_back(embed(embedder.clone(), batch));
+                }
+            }
+
+            Some(embedded) = in_flight.next() => {
+                forward(&sink, &dead_letters, embedded).await?;
+            }
+
+            _ = sleep_until(paused_until.unwrap_or_else(Instant::now)), if paused_until.is_some() => {}
+        }
+    }
+    Ok(())
+}
+
+/// Embed the texts of the posts, retried until the embedder is back when
+/// it is down. Only batches it rejects fail
+async fn embed(
+    embedder: Arc<dyn Embedder>,
+    posts: Vec<Post>,
+) -> (Vec<Post>, Result<Vec<Vec<f32>>, EmbedError>) {
+    let texts: Vec<&str> = posts.iter().map(|post| post.text.as_str()).collect();
+    let result = loop {
+        match embedder.embed_batch(&texts).await {
+            Ok(vectors) => break Ok(vectors),
+            Err(e) if e.is_transient() => {
+                warn!("Holding {} posts: {}", posts.len(), e);
+                if let Some(until) = embedder.paused_until() {
+                    sleep_until(until).await;
+                }
+            }
+            Err(e) => break Err(EmbedError::Embedding(e)),
+        }
+    };
+    (posts, result)
+}
+
+/// Send the embeddings of a batch on to be stored, or its posts to the dead letters
+async fn forward(
+    sink: &Sender<Work<(Post, Embedding)>>,
+    dead_letters: &DeadLetters,
+    (posts, result): (Vec<Post>, Result<Vec<Vec<f32>>, EmbedError>),
+) -> Result<(), EmbedError> {
+    let vectors = match result {
+        Ok(vectors) => vectors,
+        Err(e) => {
+            error!("Batch of {} posts: {}", posts.len(), e);
+            for post in &posts {
+                dead_letters.send(&dead_letter(TOPIC, post, &e, 1)).await;
             }
+            return Ok(());
+        }
+    };
+    for (post, vector) in posts.into_iter().zip(vectors) {
+        let embedding = Embedding {
+            uri: post.uri.clone(),
+            vector,
+            score: post.score,
         };
+        sink.send(Work::Item((post, embedding)))
+            .await
+            .map_err(|_| EmbedError::Stopped("store"))?;
     }
+    Ok(())
 }
 
-async fn store_task(mut embeddings: Receiver<Embedding>) {
+/// Embeddings waiting to be stored, with their posts to dead-letter them
+#[derive(Default)]
+struct Batch {
+    embeddings: Vec<Embedding>,
+    posts: Vec<Post>,
+    /// Failed inserts of this batch so far
+    failures: u32,
+}
+
+impl Batch {
+    fn push(&mut self, post: Post, embedding: Embedding) {
+        self.posts.push(post);
+        self.embeddings.push(embedding);
+    }
+
+    fn len(&self) -> usize {
+        self.embeddings.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.embeddings.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.embeddings.clear();
+        self.posts.clear();
+        self.failures = 0;
+    }
+}
+
+async fn store_task(
+    mut embeddings: Receiver<Work<(Post, Embedding)>>,
+    dead_letters: Arc<DeadLetters>,
+) {
     warn!("Ready to start storing embeddings");
     let batch_size = 100;
     let mut flush_timer = interval(Duration::from_millis(500));
+    let mut stats_timer = interval_at(Instant::now() + STATS_INTERVAL, STATS_INTERVAL);
     let pg_client = PgClient::new().await.expect("Failed to connect to db");
 
-    let mut batch = Vec::with_capacity(batch_size);
+    let mut batch = Batch::default();
     loop {
         tokio::select! {
-            Some(record) = embeddings.recv() => {
-                batch.push(record);
-                if batch.len() >= batch_size {
-                    if let Err(e) = pg_client.insert_embeddings(&batch).await {
-                        error!("Insert error: {}", e);
+            Some(work) = embeddings.recv() => {
+                match work {
+                    Work::Item((post, embedding)) => {
+                        batch.push(post, embedding);
+                        if batch.len() >= batch_size {
+                            insert_batch(&pg_client, &dead_letters, &mut batch).await;
+                            flush_timer.reset();
+                            debug!("Inserted normally");
+                        }
+                    }
+                    Work::Checkpoint(done) => {
+                        insert_batch(&pg_client, &dead_letters, &mut batch).await;
+                        // Dropping `done` keeps the offset where it is
+                        match dead_letters.flush().await {
+                            Ok(()) if batch.is_empty() => {
+                                let _ = done.send(());
+                            }
+                            Ok(()) => {}
+                            Err(e) => error!("{}", e),
+                        }
                     }
-                    flush_timer.reset();
-                    batch.clear();
-                    debug!("Inserted normally");
                 }
             }
 
             // Flush periodically even if batch isn't full
             _ = flush_timer.tick() => {
                 if !batch.is_empty() {
-                    if let Err(e) = pg_client.insert_embeddings(&batch).await {
-                        error!("Insert error: {}", e);
-                    }
-                    batch.clear();
+                    insert_batch(&pg_client, &dead_letters, &mut batch).await;
                     debug!("Inserted flushed");
                 }
             }
 
+            _ = stats_timer.tick() => {
+                info!("Inserts: {}", pg_client.take_insert_stats());
+            }
+
             // Channel closed
             else => {
                 // Final flush
-                if !batch.is_empty() {
-                    if let Err(e) = pg_client.insert_embeddings(&batch).await {
-                        error!("Final insert error: {}", e);
-                    }
-                }
+                insert_batch(&pg_client, &dead_letters, &mut batch).await;
                 break;
             }
         }
     }
 }
+
+/// Insert the batch, kept for the next try on errors so that no checkpoint
+/// is acknowledged past it. Dead-lettered once it failed `STORE_ATTEMPTS` times
+async fn insert_batch(pg_client: &PgClient, dead_letters: &DeadLetters, batch: &mut Batch) {
+    if batch.is_empty() {
+        return;
+    }
+    let e = match pg_client.insert_embeddings(&batch.embeddings).await {
+        Ok(()) => return batch.clear(),
+        Err(e) => EmbedError::Store(e),
+    };
+    batch.failures += 1;
+    error!("Insert error, attempt {}: {}", batch.failures, e);
+    if batch.failures < STORE_ATTEMPTS {
+        return;
+    }
+    info!("Dead-lettering {} posts", batch.len());
+    for post in &batch.posts {
+        dead_letters
+            .send(&dead_letter(TOPIC, post, &e, batch.failures))
+            .await;
+    }
+    batch.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("--batch-size")]
+    #[case("--concurrency")]
+    fn zero_is_rejected(#[case] flag: &str) {
+        let error = Cli::try_parse_from(["ott-embed", flag, "0"]).err().unwrap();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[rstest]
+    fn batching_defaults() {
+        let cli = Cli::try_parse_from(["ott-embed"]).unwrap();
+        assert_eq!(cli.batching.batch_size, 32);
+        assert_eq!(cli.batching.concurrency, 4);
+    }
+}
@@ -0,0 +1,290 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::http_retry::{check_status, CircuitBreaker, HttpError, Retry};
+
+/// A server with an OpenAI compatible `/v1/embeddings` API, such as vLLM,
+/// the llama.cpp server or Ollama
+#[derive(Clone)]
+pub struct OpenAiEmbedding {
+    client: Arc<reqwest::Client>,
+    url: String,
+    model: String,
+    dimensions: Option<u32>,
+    api_key: Option<String>,
+    retry: Retry,
+    breaker: Arc<CircuitBreaker>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+    index: usize,
+}
+
+impl OpenAiEmbedding {
+    /// Client of the API at `base_url`, like `http://localhost:8000/v1`,
+    /// embedding with `model`
+    pub fn new(base_url: &str, model: &str) -> Self {
+        Self {
+            client: Arc::new(
+                reqwest::Client::builder()
+                    .timeout(Duration::from_secs(30))
+                    .build()
+                    .expect("Failed to create HTTP client"),
+            ),
+            url: format!("{}/embeddings", base_url.trim_end_matches('/')),
+            model: model.to_string(),
+            dimensions: None,
+            api_key: None,
+            retry: Retry::default(),
+            breaker: Arc::default(),
+        }
+    }
+
+    /// Length of the vectors, for models that can shorten them
+    pub fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Sent as bearer token, servers run without one usually ignore it
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Arc::new(breaker);
+        self
+    }
+
+    /// Shared by the clones of this client
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Embeddings of the messages in a single request, in their order
+    pub async fn embed_batch(&self, messages: &[&str]) -> Result<Vec<Vec<f32>>, HttpError> {
+        self.retry
+            .run(&self.breaker, || self.request(messages))
+            .await
+    }
+
+    async fn request(&self, messages: &[&str]) -> Result<Vec<Vec<f32>>, HttpError> {
+        let mut payload = json!({
+            "model": self.model,
+            "input": messages,
+            "encoding_format": "float",
+        });
+        if let Some(dimensions) = self.dimensions {
+            payload["dimensions"] = json!(dimensions);
+        }
+
+        let mut request = self.client.post(&self.url).json(&payload);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request.send().await.map_err(HttpError::Transport)?;
+
+        check_status(response.status())?;
+
+        let mut data = response
+            .json::<EmbeddingsResponse>()
+            .await
+            .map_err(|e| HttpError::Decode(e.to_string()))?
+            .data;
+        if data.len() != messages.len() {
+            return Err(HttpError::Decode(format!(
+                "{} embeddings for {} inputs",
+                data.len(),
+                messages.len()
+            )));
+        }
+        // The API does not promise the order of the inputs, only their index
+        data.sort_by_key(|item| item.index);
+        if data.iter().enumerate().any(|(i, item)| item.index != i) {
+            return Err(HttpError::Decode(
+                "embedding indexes do not match".to_string(),
+            ));
+        }
+        if let Some(dimensions) = self.dimensions
+            && let Some(item) = data
+                .iter()
+                .find(|item| item.embedding.len() != dimensions as usize)
+        {
+            return Err(HttpError::Decode(format!(
+                "{} dimensions instead of {}",
+                item.embedding.len(),
+                dimensions
+            )));
+        }
+
+        Ok(data.into_iter().map(|item| item.embedding).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Json, Router,
+    };
+    use rstest::rstest;
+    use serde_json::Value;
+
+    use super::*;
+
+    const FAST_RETRY: Retry = Retry {
+        attempts: 3,
+        base: Duration::from_millis(1),
+        max: Duration::from_millis(5),
+    };
+
+    #[derive(Clone, Default)]
+    struct Stub {
+        statuses: Vec<StatusCode>,
+        /// Length of the vectors regardless of the requested dimensions
+        dimensions: Option<usize>,
+        requests: Arc<Mutex<Vec<Received>>>,
+    }
+
+    /// Body and authorization header of a request
+    type Received = (Value, Option<String>);
+
+    /// An OpenAI compatible server answering `statuses` in turn, then
+    /// embeddings of the inputs in reverse order, each filled with its index
+    async fn mock_openai(statuses: Vec<StatusCode>) -> (String, Stub) {
+        serve(Stub {
+            statuses,
+            ..Default::default()
+        })
+        .await
+    }
+
+    async fn serve(stub: Stub) -> (String, Stub) {
+        let app = Router::new().route(
+            "/v1/embeddings",
+            post(
+                |State(stub): State<Stub>, headers: HeaderMap, Json(body): Json<Value>| async move {
+                    let auth = headers
+                        .get("authorization")
+                        .map(|value| value.to_str().unwrap().to_string());
+                    let request = {
+                        let mut requests = stub.requests.lock().unwrap();
+                        requests.push((body.clone(), auth));
+                        requests.len() - 1
+                    };
+                    if let Some(status) = stub.statuses.get(request) {
+                        return Err(*status);
+                    }
+                    let dimensions = stub
+                        .dimensions
+                        .unwrap_or_else(|| body["dimensions"].as_u64().unwrap_or(4) as usize);
+                    let inputs = body["input"].as_array().unwrap().len();
+                    let data: Vec<Value> = (0..inputs)
+                        .rev()
+                        .map(|i| {
+                            json!({
+                                "object": "embedding",
+                                "index": i,
+                                "embedding": vec![i as f32; dimensions],
+                            })
+                        })
+                        .collect();
+                    Ok(Json(json!({
+                        "object": "list",
+                        "data": data,
+                        "model": body["model"],
+                    })))
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let state = stub.clone();
+        tokio::spawn(async move { axum::serve(listener, app.with_state(state)).await });
+        (url, stub)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn embeds_batches_in_order() {
+        let (url, stub) = mock_openai(vec![]).await;
+        let client = OpenAiEmbedding::new(&url, "nomic-embed-text");
+        let embeddings = client.embed_batch(&["one", "two", "three"]).await.unwrap();
+        assert_eq!(embeddings, vec![vec![0.0; 4], vec![1.0; 4], vec![2.0; 4]]);
+
+        let requests = stub.requests.lock().unwrap();
+        let (body, auth) = &requests[0];
+        assert_eq!(body["model"], "nomic-embed-text");
+        assert_eq!(body["input"], json!(["one", "two", "three"]));
+        assert!(body.get("dimensions").is_none());
+        assert_eq!(auth, &None);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn sends_dimensions_and_api_key() {
+        let (url, stub) = mock_openai(vec![]).await;
+        let client = OpenAiEmbedding::new(&format!("{}/", url), "text-embedding-3-small")
+            .with_dimensions(8)
+            .with_api_key("secret");
+        let embeddings = client.embed_batch(&["hello"]).await.unwrap();
+        assert_eq!(embeddings[0].len(), 8);
+
+        let requests = stub.requests.lock().unwrap();
+        let (body, auth) = &requests[0];
+        assert_eq!(body["dimensions"], 8);
+        assert_eq!(auth.as_deref(), Some("Bearer secret"));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn wrong_dimensions_are_rejected() {
+        let (url, _) = serve(Stub {
+            dimensions: Some(768),
+            ..Default::default()
+        })
+        .await;
+        let client = OpenAiEmbedding::new(&url, "all-minilm").with_dimensions(384);
+        let error = client.embed_batch(&["hello"]).await.unwrap_err();
+        assert!(matches!(error, HttpError::Decode(_)), "{}", error);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn retries_when_overloaded() {
+        let (url, stub) = mock_openai(vec![StatusCode::TOO_MANY_REQUESTS]).await;
+        let client = OpenAiEmbedding::new(&url, "all-minilm").with_retry(FAST_RETRY);
+        assert!(client.embed_batch(&["hello"]).await.is_ok());
+        assert_eq!(stub.requests.lock().unwrap().len(), 2);
+        assert!(client.breaker().open_until().is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejected_requests_are_not_retried() {
+        let (url, stub) = mock_openai(vec![StatusCode::UNAUTHORIZED]).await;
+        let client = OpenAiEmbedding::new(&url, "all-minilm").with_retry(FAST_RETRY);
+        let error = client.embed_batch(&["hello"]).await.unwrap_err();
+        assert!(matches!(error, HttpError::Http(StatusCode::UNAUTHORIZED)));
+        assert_eq!(stub.requests.lock().unwrap().len(), 1);
+    }
+}
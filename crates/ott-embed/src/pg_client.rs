@@ -1,36 +1,637 @@
-use anyhow::Result;
-use ott_types::Embedding;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use ott_types::{Embedding, Interaction};
 use pgvector::Vector;
-use sqlx::PgPool;
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgHasArrayType, PgTypeInfo};
+use sqlx::{Encode, PgPool, Postgres, Type};
+use tracing::warn;
+
+use crate::embedder::Embedder;
+
+/// Batches of at least this many rows are inserted with `COPY`
+const COPY_THRESHOLD: usize = 50;
+
+/// Closest rows [`PgClient::nearest_page`] ranks and pages through, the most
+/// `hnsw.ef_search` allows
+pub const NEAREST_CANDIDATES: i64 = 1000;
 
 pub struct PgClient {
     pool: PgPool,
+    copy_threshold: usize,
+    stats: Mutex<InsertStats>,
 }
 
 impl PgClient {
     pub async fn new() -> Result<Self> {
         let database_url = std::env::var("DATABASE_URL")?;
         let pool = PgPool::connect(&database_url).await?;
-        Ok(Self { pool: pool })
+        Ok(Self::with_pool(pool))
     }
 
-    pub async fn insert_embeddings(
-        self: &Self,
-        vectors: &Vec<Embedding>,
-    ) -> Result<(), sqlx::Error> {
+    pub fn with_pool(pool: PgPool) -> Self {
+        Self {
+            pool,
+            copy_threshold: COPY_THRESHOLD,
+            stats: Mutex::default(),
+        }
+    }
+
+    /// Batches of at least `rows` are inserted with `COPY`, smaller ones
+    /// with a single `INSERT`
+    pub fn with_copy_threshold(mut self, rows: usize) -> Self {
+        self.copy_threshold = rows;
+        self
+    }
+
+    /// Insert the embeddings, skipping tombstoned and already stored posts
+    pub async fn insert_embeddings(&self, embeddings: &[Embedding]) -> Result<(), sqlx::Error> {
+        if embeddings.is_empty() {
+            return Ok(());
+        }
+        let start = Instant::now();
+        if embeddings.len() >= self.copy_threshold {
+            self.copy_embeddings(embeddings).await?;
+            let mut stats = self.stats.lock().unwrap();
+            stats.copy.record(embeddings.len(), start.elapsed());
+        } else {
+            self.unnest_embeddings(embeddings).await?;
+            let mut stats = self.stats.lock().unwrap();
+            stats.unnest.record(embeddings.len(), start.elapsed());
+        }
+        Ok(())
+    }
+
+    /// One multi-row insert, cheap for small batches
+    async fn unnest_embeddings(&self, embeddings: &[Embedding]) -> Result<(), sqlx::Error> {
+        let uris: Vec<&str> = embeddings.iter().map(|e| e.uri.as_str()).collect();
+        let vectors: Vec<VectorRef> = embeddings.iter().map(|e| VectorRef(&e.vector)).collect();
+        let scores: Vec<f64> = embeddings.iter().map(|e| e.score).collect();
+
+        // Deletes can overtake the post they delete, posts delivered again
+        // keep their first row so their rank doesn't change while paging
+        sqlx::query(
+            "INSERT INTO vectors (uri, vector, score)
+             SELECT DISTINCT ON (e.uri) e.uri, e.vector, e.score
+             FROM UNNEST($1::varchar[], $2::vector[], $3::float8[]) AS e(uri, vector, score)
+             WHERE NOT EXISTS (SELECT 1 FROM deleted_posts d WHERE d.uri = e.uri)
+               AND NOT EXISTS (SELECT 1 FROM vectors v WHERE v.uri = e.uri)",
+        )
+        .bind(uris)
+        .bind(vectors)
+        .bind(scores)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Binary `COPY` into a temporary table, moved over to skip tombstoned
+    /// posts, which `COPY` can't filter
+    async fn copy_embeddings(&self, embeddings: &[Embedding]) -> Result<(), sqlx::Error> {
+        let rows = copy_rows(embeddings).map_err(sqlx::Error::Encode)?;
         let mut tx = self.pool.begin().await?;
+        // Kept by the pooled connection, emptied by every commit
+        sqlx::query(
+            "CREATE TEMP TABLE IF NOT EXISTS staged_vectors (
+                uri VARCHAR NOT NULL,
+                vector vector,
+                score DOUBLE PRECISION NOT NULL
+             ) ON COMMIT DELETE ROWS",
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // Dropped on errors, which aborts the copy
+        let mut copy = tx
+            .copy_in_raw("COPY staged_vectors (uri, vector, score) FROM STDIN (FORMAT binary)")
+            .await?;
+        copy.send(rows).await?;
+        copy.finish().await?;
 
-        for embedding in vectors {
-            let vector = Vector::from(embedding.vector.clone());
+        sqlx::query(
+            "INSERT INTO vectors (uri, vector, score)
+             SELECT DISTINCT ON (s.uri) s.uri, s.vector, s.score FROM staged_vectors s
+             WHERE NOT EXISTS (SELECT 1 FROM deleted_posts d WHERE d.uri = s.uri)
+               AND NOT EXISTS (SELECT 1 FROM vectors v WHERE v.uri = s.uri)",
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Inserts since the last call, by path
+    pub fn take_insert_stats(&self) -> InsertStats {
+        std::mem::take(&mut *self.stats.lock().unwrap())
+    }
+
+    /// Length of the vectors the table takes, none when the column has no fixed length
+    pub async fn vector_dimensions(&self) -> Result<Option<usize>, sqlx::Error> {
+        let (typmod,): (i32,) = sqlx::query_as(
+            "SELECT atttypmod FROM pg_attribute
+             WHERE attrelid = 'vectors'::regclass AND attname = 'vector'",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(usize::try_from(typmod)
+            .ok()
+            .filter(|dimensions| *dimensions > 0))
+    }
 
-            sqlx::query("INSERT INTO vectors (uri, vector) VALUES ($1, $2)")
-                .bind(&embedding.uri)
-                .bind(vector)
-                .execute(&mut *tx)
-                .await?;
+    /// Fails when `embedder` makes vectors of another length than the table
+    /// takes. An embedder that is down is not checked
+    pub async fn check_embedder(&self, embedder: &dyn Embedder) -> Result<()> {
+        let Some(dimensions) = self.vector_dimensions().await? else {
+            return Ok(());
+        };
+        match embedder.embed_batch(&["dimension check"]).await {
+            Ok(vectors) => {
+                let length = vectors.first().map_or(0, Vec::len);
+                if length != dimensions {
+                    bail!(
+                        "Embedder makes vectors of {} dimensions, the vectors table takes {}",
+                        length,
+                        dimensions
+                    );
+                }
+                Ok(())
+            }
+            Err(e) if e.is_transient() => {
+                warn!("Vector length not checked: {}", e);
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
         }
+    }
 
+    /// Tombstone the deleted posts and remove their vectors
+    pub async fn delete_posts(&self, uris: &[String]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO deleted_posts (uri) SELECT * FROM UNNEST($1::varchar[])
+             ON CONFLICT (uri) DO NOTHING",
+        )
+        .bind(uris)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM vectors WHERE uri = ANY($1)")
+            .bind(uris)
+            .execute(&mut *tx)
+            .await?;
         tx.commit().await?;
         Ok(())
     }
+
+    /// Fetch the stored embeddings for the given uris, uris that
+    /// are not in the table (yet or anymore) are simply missing from the result
+    pub async fn get_embeddings(&self, uris: &[String]) -> Result<Vec<Embedding>, sqlx::Error> {
+        let rows: Vec<(String, Vector, f64)> = sqlx::query_as(
+            "SELECT DISTINCT ON (uri) uri, vector, score FROM vectors
+             WHERE uri = ANY($1) AND vector IS NOT NULL
+             ORDER BY uri, created_at DESC",
+        )
+        .bind(uris)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uri, vector, score)| Embedding {
+                uri,
+                vector: vector.to_vec(),
+                score,
+            })
+            .collect())
+    }
+
+    /// Store interactions along with the vector of their item, when the
+    /// item is still in the vectors table
+    pub async fn insert_interactions(
+        &self,
+        interactions: &[Interaction],
+    ) -> Result<(), sqlx::Error> {
+        let dids: Vec<&str> = interactions.iter().map(|i| i.did.as_str()).collect();
+        let items: Vec<&str> = interactions.iter().map(|i| i.item.as_str()).collect();
+        let events: Vec<&str> = interactions.iter().map(|i| i.event.as_str()).collect();
+        let contexts: Vec<Option<&str>> = interactions
+            .iter()
+            .map(|i| i.feed_context.as_deref())
+            .collect();
+
+        sqlx::query(
+            "INSERT INTO interactions (did, item, event, feed_context, vector)
+             SELECT i.did, i.item, i.event, i.feed_context,
+                 (SELECT vector FROM vectors
+                  WHERE uri = i.item AND vector IS NOT NULL
+                  ORDER BY created_at DESC LIMIT 1)
+             FROM UNNEST($1::varchar[], $2::varchar[], $3::varchar[], $4::varchar[])
+                 AS i(did, item, event, feed_context)",
+        )
+        .bind(dids)
+        .bind(items)
+        .bind(events)
+        .bind(contexts)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Vectors of the items `did` most recently sent `event` for
+    pub async fn get_interaction_embeddings(
+        &self,
+        did: &str,
+        event: &str,
+        limit: i64,
+    ) -> Result<Vec<Embedding>, sqlx::Error> {
+        let rows: Vec<(String, Vector)> = sqlx::query_as(
+            "SELECT item, vector FROM (
+                SELECT DISTINCT ON (item) item, vector, created_at FROM interactions
+                WHERE did = $1 AND event = $2 AND vector IS NOT NULL
+                ORDER BY item, created_at DESC
+             ) AS latest
+             ORDER BY created_at DESC
+             LIMIT $3",
+        )
+        .bind(did)
+        .bind(event)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uri, vector)| Embedding {
+                uri,
+                vector: vector.to_vec(),
+                // Interactions only keep the vector
+                score: 0.0,
+            })
+            .collect())
+    }
+
+    pub async fn insert_session(&self, id: i64, session: &FeedSession) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO feed_sessions (id, did, profile, exclude) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(id)
+        .bind(&session.did)
+        .bind(VectorRef(&session.profile))
+        .bind(&session.exclude)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The session `id`, none when it does not exist or is older than `ttl`
+    pub async fn get_session(
+        &self,
+        id: i64,
+        ttl: Duration,
+    ) -> Result<Option<FeedSession>, sqlx::Error> {
+        let row: Option<(String, Vector, Vec<String>)> = sqlx::query_as(
+            "SELECT did, profile, exclude FROM feed_sessions
+             WHERE id = $1 AND created_at > NOW() - make_interval(secs => $2)",
+        )
+        .bind(id)
+        .bind(ttl.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(did, profile, exclude)| FeedSession {
+            did,
+            profile: profile.to_vec(),
+            exclude,
+        }))
+    }
+
+    /// A page of nearest neighbours, lowest score first.
+    ///
+    /// The [`NEAREST_CANDIDATES`] rows closest to `vector` are found with the
+    /// HNSW index, then ranked and paged. Pages are keyed on `(score, uri)`
+    /// of the last post of the previous page and only consider rows created
+    /// before `as_of` (unix micros), so paging is stable while new rows
+    /// arrive and old partitions are dropped. Posts have a single row, see
+    /// [`PgClient::insert_embeddings`], deleted posts are never returned.
+    ///
+    /// The score is the distance adjusted by `ranking`, see [`Ranking`].
+    pub async fn nearest_page(
+        &self,
+        vector: &[f32],
+        exclude: &[String],
+        as_of: i64,
+        ranking: Ranking,
+        after: Option<(f64, &str)>,
+        limit: i64,
+    ) -> Result<Vec<Neighbour>, sqlx::Error> {
+        let (score, uri) = after.unwrap_or((f64::NEG_INFINITY, ""));
+        let mut tx = self.pool.begin().await?;
+        // The index scan goes on until enough rows pass the filters, in
+        // roughly the order of distance, which the ranking sorts out anyway
+        sqlx::query(
+            "SELECT set_config('hnsw.ef_search', $1, true),
+                    set_config('hnsw.iterative_scan', 'relaxed_order', true)",
+        )
+        .bind(NEAREST_CANDIDATES.to_string())
+        .execute(&mut *tx)
+        .await?;
+        let rows = sqlx::query_as(
+            "SELECT uri, score FROM (
+                SELECT DISTINCT ON (uri) uri,
+                    distance
+                    + $4 * EXTRACT(EPOCH FROM to_timestamp($3::float8 / 1000000) - created_at) / 3600
+                    - $5 * LN(1 + velocity)
+                    AS score
+                FROM (
+                    SELECT id, uri, created_at, score AS velocity, vector <=> $1 AS distance
+                    FROM vectors
+                    WHERE vector IS NOT NULL
+                      AND created_at <= to_timestamp($3::float8 / 1000000)
+                    ORDER BY vector <=> $1
+                    LIMIT $9
+                ) AS nearest
+                WHERE uri <> ALL($2)
+                  AND NOT EXISTS (SELECT 1 FROM deleted_posts d WHERE d.uri = nearest.uri)
+                ORDER BY uri, created_at
+             ) AS candidates
+             WHERE (score, uri) > ($6, $7)
+             ORDER BY score, uri
+             LIMIT $8",
+        )
+        .bind(Vector::from(vector.to_vec()))
+        .bind(exclude)
+        .bind(as_of)
+        .bind(ranking.recency_weight)
+        .bind(ranking.velocity_weight)
+        .bind(score)
+        .bind(uri)
+        .bind(limit)
+        .bind(NEAREST_CANDIDATES)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(rows)
+    }
+}
+
+/// How nearest neighbours are ranked besides their cosine distance
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ranking {
+    /// Added per hour a post is older than the page, favours recent posts
+    pub recency_weight: f64,
+    /// Subtracted times `ln(1 + like velocity)`, favours posts liked fast
+    pub velocity_weight: f64,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Neighbour {
+    pub uri: String,
+    pub score: f64,
+}
+
+/// The query a user pages a feed with, kept so that later pages are ranked
+/// against the same vector as the first one
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedSession {
+    pub did: String,
+    pub profile: Vec<f32>,
+    /// Posts liked or asked to see less of, never served
+    pub exclude: Vec<String>,
+}
+
+/// Inserts made with each path of [`PgClient::insert_embeddings`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InsertStats {
+    pub unnest: PathStats,
+    pub copy: PathStats,
+}
+
+impl fmt::Display for InsertStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unnest {}, copy {}", self.unnest, self.copy)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathStats {
+    pub batches: u64,
+    pub rows: u64,
+    pub elapsed: Duration,
+}
+
+impl PathStats {
+    fn record(&mut self, rows: usize, elapsed: Duration) {
+        self.batches += 1;
+        self.rows += rows as u64;
+        self.elapsed += elapsed;
+    }
+}
+
+impl fmt::Display for PathStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rows in {} batches", self.rows, self.batches)?;
+        if self.rows > 0 {
+            let per_row = self.elapsed.as_secs_f64() * 1e6 / self.rows as f64;
+            write!(f, ", {:.1}µs per row", per_row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Binds an embedding's vector without copying it into a [`Vector`]
+struct VectorRef<'a>(&'a [f32]);
+
+impl Type<Postgres> for VectorRef<'_> {
+    fn type_info() -> PgTypeInfo {
+        Vector::type_info()
+    }
+}
+
+impl PgHasArrayType for VectorRef<'_> {
+    fn array_type_info() -> PgTypeInfo {
+        Vector::array_type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for VectorRef<'_> {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        encode_vector(self.0, buf)?;
+        Ok(IsNull::No)
+    }
+}
+
+/// pgvector's binary format: dimensions, a reserved `0` and the values
+fn encode_vector(vector: &[f32], buf: &mut Vec<u8>) -> Result<(), BoxDynError> {
+    buf.extend(u16::try_from(vector.len())?.to_be_bytes());
+    buf.extend(0u16.to_be_bytes());
+    for value in vector {
+        buf.extend(value.to_be_bytes());
+    }
+    Ok(())
+}
+
+/// The embeddings as rows of `(uri, vector, score)` in the binary `COPY` format
+fn copy_rows(embeddings: &[Embedding]) -> Result<Vec<u8>, BoxDynError> {
+    let mut buf = Vec::with_capacity(
+        19 + embeddings
+            .iter()
+            .map(|e| 2 + 4 + e.uri.len() + 4 + 4 + 4 * e.vector.len() + 4 + 8)
+            .sum::<usize>()
+            + 2,
+    );
+    // Signature, flags and header extension length
+    buf.extend(b"PGCOPY\n\xff\r\n\0");
+    buf.extend(0u32.to_be_bytes());
+    buf.extend(0u32.to_be_bytes());
+    for embedding in embeddings {
+        buf.extend(3u16.to_be_bytes());
+        buf.extend(u32::try_from(embedding.uri.len())?.to_be_bytes());
+        buf.extend(embedding.uri.as_bytes());
+        buf.extend(u32::try_from(4 + 4 * embedding.vector.len())?.to_be_bytes());
+        encode_vector(&embedding.vector, &mut buf)?;
+        buf.extend(8u32.to_be_bytes());
+        buf.extend(embedding.score.to_be_bytes());
+    }
+    buf.extend((-1i16).to_be_bytes());
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use sqlx::postgres::PgPoolOptions;
+    use sqlx::Executor;
+
+    use super::*;
+    use crate::embedder::HashEmbedder;
+
+    fn embeddings(count: usize) -> Vec<Embedding> {
+        (0..count)
+            .map(|i| Embedding {
+                uri: format!("at://did:plc:someone/app.bsky.feed.post/{}", i),
+                vector: vec![i as f32, 0.5, -1.0],
+                score: i as f64,
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn copy_rows_are_framed() {
+        let rows = copy_rows(&embeddings(2)).unwrap();
+        assert!(rows.starts_with(b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0"));
+        assert!(rows.ends_with(&[0xff, 0xff]));
+        let uri = "at://did:plc:someone/app.bsky.feed.post/0";
+        let row = 2 + 4 + uri.len() + 4 + 4 + 3 * 4 + 4 + 8;
+        assert_eq!(rows.len(), 19 + 2 * row + 2);
+
+        let first = &rows[19..19 + row];
+        assert_eq!(&first[..2], &3u16.to_be_bytes());
+        assert_eq!(&first[6..6 + uri.len()], uri.as_bytes());
+        let vector = &first[6 + uri.len() + 4..][..16];
+        assert_eq!(&vector[..4], &[0, 3, 0, 0]);
+        assert_eq!(&vector[8..12], &0.5f32.to_be_bytes());
+    }
+
+    #[rstest]
+    fn vectors_too_long_fail_to_encode() {
+        let embedding = Embedding {
+            uri: "at://did:plc:someone/app.bsky.feed.post/0".to_string(),
+            vector: vec![0.0; 70_000],
+            score: 0.0,
+        };
+        assert!(copy_rows(&[embedding]).is_err());
+    }
+
+    /// Both insert paths against the schema of the migrations, in a disposable
+    /// instance of the Postgres image with pgvector, pg_partman and pg_cron.
+    /// Each run has posts of its own, the tables may hold others
+    #[rstest]
+    #[case("unnest")]
+    #[case("copy")]
+    #[ignore = "needs a disposable instance of the Postgres image at DATABASE_URL"]
+    #[tokio::test]
+    async fn inserts_all_but_deleted_posts(#[case] path: &str) {
+        let copy_threshold = if path == "copy" { 1 } else { usize::MAX };
+        let pool = migrated_pool().await;
+
+        let prefix = format!("at://did:plc:test{}{}/", path, rand::random::<u32>());
+        let posts: Vec<Embedding> = embeddings(120)
+            .into_iter()
+            .enumerate()
+            .map(|(i, embedding)| Embedding {
+                uri: format!("{}{}", prefix, i),
+                vector: vec![i as f32; 384],
+                ..embedding
+            })
+            .collect();
+        let client = PgClient::with_pool(pool.clone()).with_copy_threshold(copy_threshold);
+        client.delete_posts(&[posts[7].uri.clone()]).await.unwrap();
+        client.insert_embeddings(&posts[..60]).await.unwrap();
+        client.insert_embeddings(&posts[60..]).await.unwrap();
+
+        let like = format!("{}%", prefix);
+        let (rows,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM vectors WHERE uri LIKE $1")
+            .bind(&like)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 119);
+        let stored = client
+            .get_embeddings(&[posts[42].uri.clone()])
+            .await
+            .unwrap();
+        assert_eq!(stored[0].vector, posts[42].vector);
+        assert_eq!(stored[0].score, 42.0);
+
+        let stats = client.take_insert_stats();
+        let used = if path == "copy" {
+            stats.copy
+        } else {
+            stats.unnest
+        };
+        assert_eq!((used.batches, used.rows), (2, 120));
+        assert_eq!(client.take_insert_stats().copy.rows, 0);
+
+        for table in ["vectors", "deleted_posts"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE uri LIKE $1", table))
+                .bind(&like)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[rstest]
+    #[ignore = "needs a disposable instance of the Postgres image at DATABASE_URL"]
+    #[tokio::test]
+    async fn embedders_have_to_fit_the_table() {
+        let client = PgClient::with_pool(migrated_pool().await);
+        assert_eq!(client.vector_dimensions().await.unwrap(), Some(384));
+        assert!(client.check_embedder(&HashEmbedder::new(384)).await.is_ok());
+        assert!(client
+            .check_embedder(&HashEmbedder::new(768))
+            .await
+            .is_err());
+    }
+
+    /// A pool of the database at `DATABASE_URL`, migrated like production
+    async fn migrated_pool() -> PgPool {
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .connect(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        pool.execute(
+            "DO $$ BEGIN CREATE ROLE app; EXCEPTION WHEN duplicate_object THEN NULL; END $$",
+        )
+        .await
+        .unwrap();
+        sqlx::migrate!("../ott-db-migration/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+        pool
+    }
 }
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use fluvio::{Fluvio, RecordKey, TopicProducerPool};
+use ott_stream::{partition_count, ConsumerOptions, TopicConsumer};
+use ott_types::{DeadLetter, Post};
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+use crate::error::EmbedError;
+
+/// Dead letter of a post consumed from `topic` that failed `attempts` more
+/// times, on top of the attempts before it was replayed
+pub fn dead_letter(topic: &str, post: &Post, error: &EmbedError, attempts: u32) -> DeadLetter {
+    let attempts = post.attempts + attempts;
+    DeadLetter {
+        topic: topic.to_string(),
+        partition: None,
+        offset: None,
+        reason: error.reason().to_string(),
+        error: error.to_string(),
+        attempts,
+        value: serde_json::to_string(&Post {
+            attempts,
+            ..post.clone()
+        })
+        .expect("Posts serialize"),
+    }
+}
+
+/// Whether a letter is replayed, malformed and rejected records would only
+/// fail again, as would letters tried `max_attempts` times already
+pub fn replays(letter: &DeadLetter, reasons: &[String], max_attempts: u32) -> bool {
+    reasons.contains(&letter.reason) && letter.attempts < max_attempts
+}
+
+/// Publish the letters of `dlq` failed for one of `reasons` fewer than
+/// `max_attempts` times again to the topics they were consumed from, posts
+/// with their attempts so far, until none arrived for `idle`. The
+/// offsets are committed for the group of `options` afterwards, so the next
+/// replay continues with the letters after these, skipped ones included.
+/// Returns how many letters were replayed.
+pub async fn replay(
+    fluvio: &Fluvio,
+    dlq: &str,
+    options: &ConsumerOptions,
+    reasons: &[String],
+    max_attempts: u32,
+    idle: Duration,
+) -> Result<usize> {
+    let partitions: Vec<u32> = (0..partition_count(fluvio, dlq).await?).collect();
+    let mut consumer = TopicConsumer::connect(fluvio, dlq, &partitions, options).await?;
+    let mut producers: HashMap<String, TopicProducerPool> = HashMap::new();
+    let mut replayed = 0;
+    let mut skipped = 0;
+    while let Ok(Some(record)) = timeout(idle, consumer.next()).await {
+        let record = record?;
+        let letter = match serde_json::from_slice::<DeadLetter>(record.value()) {
+            Ok(letter) => letter,
+            Err(e) => {
+                warn!("Skipping letter at {}: {}", record.offset(), e);
+                skipped += 1;
+                continue;
+            }
+        };
+        if !replays(&letter, reasons, max_attempts) {
+            skipped += 1;
+            continue;
+        }
+        if !producers.contains_key(&letter.topic) {
+            let producer = fluvio.topic_producer(&letter.topic).await?;
+            producers.insert(letter.topic.clone(), producer);
+        }
+        producers[&letter.topic]
+            .send(RecordKey::NULL, letter.value)
+            .await
+            .map_err(|e| anyhow!("Failed to replay to {}: {}", letter.topic, e))?;
+        replayed += 1;
+    }
+    for (topic, producer) in &producers {
+        producer
+            .flush()
+            .await
+            .map_err(|e| anyhow!("Failed to flush {}: {}", topic, e))?;
+    }
+    consumer.commit().await?;
+    info!("Replayed {} letters, skipped {}", replayed, skipped);
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_retry::HttpError;
+    use reqwest::StatusCode;
+    use rstest::rstest;
+
+    #[rstest]
+    fn letters_keep_the_post() {
+        let post = Post {
+            did: "did:plc:someone".to_string(),
+            uri: "at://did:plc:someone/app.bsky.feed.post/3m2y6a5h6os27".to_string(),
+            text: "hello world".to_string(),
+            count: 3,
+            score: 2.5,
+            ..Default::default()
+        };
+        let error = EmbedError::Embedding(HttpError::Http(StatusCode::PAYLOAD_TOO_LARGE).into());
+        let letter = dead_letter("posts", &post, &error, 1);
+        assert_eq!(letter.reason, "rejected");
+        assert_eq!(letter.attempts, 1);
+        let replayed = serde_json::from_str::<Post>(&letter.value).unwrap();
+        assert_eq!(
+            replayed,
+            Post {
+                attempts: 1,
+                ..post
+            }
+        );
+    }
+
+    #[rstest]
+    fn replayed_posts_count_on() {
+        let post = Post {
+            attempts: 3,
+            ..Default::default()
+        };
+        let error =
+            EmbedError::Embedding(HttpError::Overloaded(StatusCode::SERVICE_UNAVAILABLE).into());
+        let letter = dead_letter("posts", &post, &error, 2);
+        assert_eq!(letter.reason, "embedding");
+        assert_eq!(letter.attempts, 5);
+        assert_eq!(
+            serde_json::from_str::<Post>(&letter.value)
+                .unwrap()
+                .attempts,
+            5
+        );
+    }
+
+    #[rstest]
+    #[case("embedding", 1, true)]
+    #[case("store", 3, true)]
+    #[case("store", 5, false)]
+    #[case("rejected", 1, false)]
+    #[case("malformed", 1, false)]
+    fn replays_by_reason(#[case] reason: &str, #[case] attempts: u32, #[case] expected: bool) {
+        let letter = DeadLetter {
+            topic: "posts".to_string(),
+            partition: Some(0),
+            offset: Some(41),
+            reason: reason.to_string(),
+            error: String::new(),
+            attempts,
+            value: String::new(),
+        };
+        let reasons = vec!["embedding".to_string(), "store".to_string()];
+        assert_eq!(replays(&letter, &reasons, 5), expected);
+    }
+}
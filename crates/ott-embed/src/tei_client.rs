@@ -2,10 +2,14 @@ use serde_json::json;
 use std::sync::Arc;
 use std::time::Duration;
 
+use crate::http_retry::{check_status, CircuitBreaker, HttpError, Retry};
+
 #[derive(Clone)]
 pub struct TextEmbedding {
     client: Arc<reqwest::Client>,
     url: String,
+    retry: Retry,
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl TextEmbedding {
@@ -18,13 +22,43 @@ impl TextEmbedding {
                     .build()
                     .expect("Failed to create HTTP client"),
             ),
+            retry: Retry::default(),
+            breaker: Arc::default(),
         }
     }
 
-    pub async fn embed(&self, message: &str) -> Result<Vec<f32>, String> {
-        // Create JSON payload
+    pub fn with_retry(mut self, retry: Retry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Arc::new(breaker);
+        self
+    }
+
+    /// Shared by the clones of this client
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    pub async fn embed(&self, message: &str) -> Result<Vec<f32>, HttpError> {
+        Ok(self.embed_batch(&[message]).await?.remove(0))
+    }
+
+    /// Embeddings of the messages in a single request, in their order.
+    /// TEI rejects more than its `--max-client-batch-size` (32 by default)
+    pub async fn embed_batch(&self, messages: &[&str]) -> Result<Vec<Vec<f32>>, HttpError> {
+        self.retry
+            .run(&self.breaker, || self.request(messages))
+            .await
+    }
+
+    async fn request(&self, messages: &[&str]) -> Result<Vec<Vec<f32>>, HttpError> {
+        // Create JSON payload, too long messages are cut instead of failing the batch
         let payload = json!({
-            "inputs": message,
+            "inputs": messages,
+            "truncate": true,
         });
 
         // Send POST request
@@ -35,13 +69,21 @@ impl TextEmbedding {
             .json(&payload)
             .send()
             .await
-            .map_err(|error| error.to_string())?;
+            .map_err(HttpError::Transport)?;
+
+        check_status(response.status())?;
 
         let data = response
             .json::<Vec<Vec<f32>>>()
             .await
-            .map_err(|e| e.to_string())?[0]
-            .clone();
+            .map_err(|e| HttpError::Decode(e.to_string()))?;
+        if data.len() != messages.len() {
+            return Err(HttpError::Decode(format!(
+                "{} embeddings for {} inputs",
+                data.len(),
+                messages.len()
+            )));
+        }
 
         Ok(data)
     }
@@ -49,9 +91,13 @@ impl TextEmbedding {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
     use rstest::{fixture, rstest};
+    use serde_json::Value;
 
-    use crate::tei_client::TextEmbedding;
+    use super::*;
 
     #[fixture]
     fn tei_url() -> String {
@@ -69,4 +115,95 @@ mod tests {
         let resp = tei_client.embed("This is a test").await;
         assert!(resp.is_ok());
     }
+
+    const FAST_RETRY: Retry = Retry {
+        attempts: 3,
+        base: Duration::from_millis(1),
+        max: Duration::from_millis(5),
+    };
+
+    /// A TEI answering `statuses` in turn, then embeddings of the inputs
+    async fn mock_tei(statuses: Vec<StatusCode>) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let state = (statuses, requests.clone());
+        let app = Router::new().route(
+            "/embed",
+            post(
+                |State((statuses, requests)): State<(Vec<StatusCode>, Arc<AtomicUsize>)>,
+                 Json(body): Json<Value>| async move {
+                    let request = requests.fetch_add(1, Ordering::SeqCst);
+                    if let Some(status) = statuses.get(request) {
+                        return Err(*status);
+                    }
+                    let inputs = body["inputs"].as_array().unwrap().len();
+                    Ok(Json(vec![vec![0.5f32; 4]; inputs]))
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/embed", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app.with_state(state)).await });
+        (url, requests)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn embeds_batches() {
+        let (url, _) = mock_tei(vec![]).await;
+        let client = TextEmbedding::new(&url);
+        let embeddings = client.embed_batch(&["one", "two", "three"]).await.unwrap();
+        assert_eq!(embeddings.len(), 3);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn retries_when_overloaded() {
+        let (url, requests) = mock_tei(vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TOO_MANY_REQUESTS,
+        ])
+        .await;
+        let client = TextEmbedding::new(&url).with_retry(FAST_RETRY);
+        assert!(client.embed("hello").await.is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejected_requests_are_not_retried() {
+        let (url, requests) = mock_tei(vec![StatusCode::PAYLOAD_TOO_LARGE]).await;
+        let client = TextEmbedding::new(&url).with_retry(FAST_RETRY);
+        let error = client.embed("hello").await.unwrap_err();
+        assert!(matches!(
+            error,
+            HttpError::Http(StatusCode::PAYLOAD_TOO_LARGE)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn circuit_opens_while_down_and_closes_when_back() {
+        let (url, _) = mock_tei(vec![StatusCode::SERVICE_UNAVAILABLE; 3]).await;
+        let client = TextEmbedding::new(&url)
+            .with_retry(FAST_RETRY)
+            .with_breaker(CircuitBreaker::new(3, Duration::from_secs(60)));
+        let error = client.embed("hello").await.unwrap_err();
+        assert!(matches!(error, HttpError::Overloaded(_)));
+        assert!(client.breaker().open_until().is_some());
+
+        assert!(client.embed("hello").await.is_ok());
+        assert!(client.breaker().open_until().is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn unreachable_is_transient() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/embed", listener.local_addr().unwrap());
+        drop(listener);
+        let client = TextEmbedding::new(&url).with_retry(FAST_RETRY);
+        let error = client.embed("hello").await.unwrap_err();
+        assert!(error.is_transient(), "{}", error);
+    }
 }
@@ -0,0 +1,424 @@
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use moka::{ops::compute::Op, sync::Cache};
+use ott_types::{Commit, DeletedPost, Like, Post, RawPost};
+use serde::{Deserialize, Serialize};
+
+use crate::rules::{FilterConfig, Rule};
+use crate::velocity;
+
+/// Source of the current time, unix micros
+pub trait Clock {
+    fn now_us(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_us(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_micros() as u64
+    }
+}
+
+/// What to publish after an event
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Send the post on to be embedded
+    Forward(Post),
+    /// Publish the delete, also when the post is not tracked as it may
+    /// have been forwarded already
+    Delete(DeletedPost),
+    /// A user of the feeds liked a post that was never seen or is gone
+    /// from the cache, fetch it by uri and send it on
+    Fetch(String),
+}
+
+/// A post counting likes, since `seen_at_us`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tracked {
+    pub seen_at_us: u64,
+    pub post: Post,
+}
+
+/// Decides which posts are forwarded, from the post and like events and
+/// the time of `clock`. Knows nothing about topics, the caller feeds it
+/// records and publishes its decisions.
+pub struct FilterEngine<C = SystemClock> {
+    rule: Rule,
+    window: Duration,
+    half_life: Duration,
+    clock: C,
+    /// Posts not forwarded yet. The TTL only bounds memory, posts are
+    /// expired by `clock` so replays do not depend on wall time
+    posts: Cache<String, Tracked>,
+    vip_users: HashSet<String>,
+}
+
+impl<C: Clock> FilterEngine<C> {
+    pub fn new(config: &FilterConfig, clock: C) -> Self {
+        Self {
+            rule: config.rule.clone(),
+            window: config.window,
+            half_life: config.half_life,
+            clock,
+            posts: Cache::builder().time_to_live(config.window).build(),
+            vip_users: HashSet::new(),
+        }
+    }
+
+    pub fn on_post(&self, raw: RawPost) -> Option<Decision> {
+        let now = self.clock.now_us();
+        match raw.commit {
+            Commit::Create { record } => {
+                let mut forward = None;
+                self.posts.entry(raw.uri.clone()).and_compute_with(
+                    |maybe_entry| match maybe_entry {
+                        Some(entry) if !self.expired(entry.value(), now) => Op::Nop,
+                        _ => {
+                            let post = Post {
+                                uri: raw.uri,
+                                did: raw.did,
+                                text: record.text,
+                                scored_at_us: event_time(raw.time_us, now),
+                                langs: record.langs.unwrap_or_default(),
+                                reply: record.reply.is_some(),
+                                ..Default::default()
+                            };
+                            if self.rule.matches(&post) {
+                                forward = Some(Decision::Forward(post));
+                                Op::Nop
+                            } else {
+                                Op::Put(Tracked {
+                                    seen_at_us: now,
+                                    post,
+                                })
+                            }
+                        }
+                    },
+                );
+                forward
+            }
+            Commit::Delete => {
+                self.posts.invalidate(&raw.uri);
+                Some(Decision::Delete(DeletedPost { uri: raw.uri }))
+            }
+            Commit::Update => None,
+        }
+    }
+
+    pub fn on_like(&self, like: Like) -> Option<Decision> {
+        let now = self.clock.now_us();
+        let vip = self.vip_users.contains(&like.did);
+        let mut forward = None;
+        self.posts
+            .entry(like.uri.clone())
+            .and_compute_with(|maybe_entry| match maybe_entry {
+                // Liked by a user of the feeds, their likes are always embedded
+                Some(entry) if vip && self.expired(entry.value(), now) => {
+                    forward = Some(Decision::Forward(entry.into_value().post));
+                    Op::Remove
+                }
+                Some(entry) if self.expired(entry.value(), now) => Op::Remove,
+                Some(entry) => {
+                    let mut tracked = entry.into_value();
+                    velocity::add_like(
+                        &mut tracked.post,
+                        event_time(like.time_us, now),
+                        self.half_life,
+                    );
+                    if vip || self.rule.matches(&tracked.post) {
+                        forward = Some(Decision::Forward(tracked.post));
+                        Op::Remove
+                    } else {
+                        Op::Put(tracked)
+                    }
+                }
+                None if vip => {
+                    forward = Some(Decision::Fetch(like.uri));
+                    Op::Nop
+                }
+                None => Op::Nop, // Not tracked, forwarded or out of the window
+            });
+        forward
+    }
+
+    /// Forward every post `did` likes from now on, false if it already was
+    pub fn add_vip(&mut self, did: String) -> bool {
+        self.vip_users.insert(did)
+    }
+
+    /// Track the posts of a snapshot, except those out of the window by now
+    pub fn restore(&self, posts: Vec<Tracked>) -> usize {
+        let now = self.clock.now_us();
+        posts
+            .into_iter()
+            .filter(|tracked| !self.expired(tracked, now))
+            .map(|tracked| self.posts.insert(tracked.post.uri.clone(), tracked))
+            .count()
+    }
+
+    /// The posts tracked, for a snapshot
+    pub fn tracked(&self) -> Vec<Tracked> {
+        let now = self.clock.now_us();
+        self.posts
+            .iter()
+            .map(|(_, tracked)| tracked)
+            .filter(|tracked| !self.expired(tracked, now))
+            .collect()
+    }
+
+    pub fn now_us(&self) -> u64 {
+        self.clock.now_us()
+    }
+
+    fn expired(&self, tracked: &Tracked, now: u64) -> bool {
+        now.saturating_sub(tracked.seen_at_us) > self.window.as_micros() as u64
+    }
+}
+
+/// Time of an event, records without one happened now
+fn event_time(time_us: u64, now: u64) -> u64 {
+    if time_us == 0 {
+        now
+    } else {
+        time_us
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ott_types::Record;
+    use rstest::{fixture, rstest};
+    use serde_json::Value;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const MINUTE_US: u64 = 60 * 1_000_000;
+    const T0: u64 = 1_759_320_000_000_000;
+    const CAPTURE: &str = include_str!("../fixtures/jetstream-capture.jsonl");
+
+    /// Set by the test, shared with the engine
+    #[derive(Clone, Default)]
+    struct ManualClock(Rc<Cell<u64>>);
+
+    impl Clock for ManualClock {
+        fn now_us(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[fixture]
+    fn clock() -> ManualClock {
+        let clock = ManualClock::default();
+        clock.0.set(T0);
+        clock
+    }
+
+    fn engine(rule: &str, clock: &ManualClock) -> FilterEngine<ManualClock> {
+        let config = FilterConfig::from_toml(rule).unwrap();
+        FilterEngine::new(&config, clock.clone())
+    }
+
+    fn create(uri: &str, text: &str) -> RawPost {
+        RawPost {
+            did: "did:plc:author".to_string(),
+            uri: uri.to_string(),
+            time_us: 0,
+            commit: Commit::Create {
+                record: Record {
+                    text: text.to_string(),
+                    langs: Some(vec!["en".to_string()]),
+                    reply: None,
+                },
+            },
+        }
+    }
+
+    fn like(did: &str, uri: &str) -> Like {
+        Like {
+            did: did.to_string(),
+            uri: uri.to_string(),
+            time_us: 0,
+        }
+    }
+
+    fn forwarded(decision: Option<Decision>) -> Option<String> {
+        match decision {
+            Some(Decision::Forward(post)) => Some(post.uri),
+            _ => None,
+        }
+    }
+
+    #[rstest]
+    fn forwards_once_liked_enough(clock: ManualClock) {
+        let engine = engine("rule = { min_likes = 2 }", &clock);
+        assert_eq!(engine.on_post(create("at://a", "hello")), None);
+        assert_eq!(engine.on_like(like("did:plc:1", "at://a")), None);
+        assert_eq!(
+            forwarded(engine.on_like(like("did:plc:2", "at://a"))),
+            Some("at://a".to_string())
+        );
+        // Forwarded posts are not tracked anymore
+        assert_eq!(engine.on_like(like("did:plc:3", "at://a")), None);
+    }
+
+    #[rstest]
+    fn matching_posts_forward_on_create(clock: ManualClock) {
+        let engine = engine("rule = { min_text_len = 3 }", &clock);
+        assert_eq!(
+            forwarded(engine.on_post(create("at://a", "hello"))),
+            Some("at://a".to_string())
+        );
+        assert!(engine.tracked().is_empty());
+    }
+
+    #[rstest]
+    fn vip_likes_forward(clock: ManualClock) {
+        let mut engine = engine("rule = { min_likes = 100 }", &clock);
+        engine.on_post(create("at://a", "hello"));
+        assert!(engine.add_vip("did:plc:vip".to_string()));
+        assert!(!engine.add_vip("did:plc:vip".to_string()));
+        assert_eq!(engine.on_like(like("did:plc:1", "at://a")), None);
+        assert_eq!(
+            forwarded(engine.on_like(like("did:plc:vip", "at://a"))),
+            Some("at://a".to_string())
+        );
+    }
+
+    #[rstest]
+    fn vip_likes_fetch_posts_never_seen(clock: ManualClock) {
+        let mut engine = engine("rule = { min_likes = 100 }", &clock);
+        engine.add_vip("did:plc:vip".to_string());
+        assert_eq!(engine.on_like(like("did:plc:1", "at://a")), None);
+        assert_eq!(
+            engine.on_like(like("did:plc:vip", "at://a")),
+            Some(Decision::Fetch("at://a".to_string()))
+        );
+    }
+
+    #[rstest]
+    fn vip_likes_forward_posts_out_of_the_window(clock: ManualClock) {
+        let mut engine = engine("window = \"10m\"\nrule = { min_likes = 100 }", &clock);
+        engine.add_vip("did:plc:vip".to_string());
+        engine.on_post(create("at://a", "hello"));
+        clock.0.set(T0 + 11 * MINUTE_US);
+        assert_eq!(
+            forwarded(engine.on_like(like("did:plc:vip", "at://a"))),
+            Some("at://a".to_string())
+        );
+        assert!(engine.tracked().is_empty());
+    }
+
+    #[rstest]
+    fn posts_expire_by_the_clock(clock: ManualClock) {
+        let engine = engine("window = \"10m\"\nrule = { min_likes = 1 }", &clock);
+        engine.on_post(create("at://a", "hello"));
+        clock.0.set(T0 + 11 * MINUTE_US);
+        assert_eq!(engine.on_like(like("did:plc:1", "at://a")), None);
+        assert!(engine.tracked().is_empty());
+    }
+
+    #[rstest]
+    fn deletes_untrack_and_publish(clock: ManualClock) {
+        let engine = engine("rule = { min_likes = 1 }", &clock);
+        engine.on_post(create("at://a", "hello"));
+        let delete = RawPost {
+            commit: Commit::Delete,
+            ..create("at://a", "")
+        };
+        assert_eq!(
+            engine.on_post(delete),
+            Some(Decision::Delete(DeletedPost {
+                uri: "at://a".to_string()
+            }))
+        );
+        assert_eq!(engine.on_like(like("did:plc:1", "at://a")), None);
+    }
+
+    #[rstest]
+    fn restores_tracked_posts(clock: ManualClock) {
+        let engine = engine("rule = { min_likes = 2 }", &clock);
+        engine.on_post(create("at://a", "hello"));
+        engine.on_like(like("did:plc:1", "at://a"));
+
+        let restored = self::engine("rule = { min_likes = 2 }", &clock);
+        assert_eq!(restored.restore(engine.tracked()), 1);
+        assert_eq!(
+            forwarded(restored.on_like(like("did:plc:2", "at://a"))),
+            Some("at://a".to_string())
+        );
+    }
+
+    /// Feed a jetstream capture, with the clock following the records
+    fn replay(engine: &FilterEngine<ManualClock>, clock: &ManualClock) -> Vec<Decision> {
+        CAPTURE
+            .lines()
+            .filter_map(|line| {
+                let value: Value = serde_json::from_str(line).unwrap();
+                clock.0.set(value["time_us"].as_u64().unwrap());
+                match value["commit"]["collection"].as_str() {
+                    Some("app.bsky.feed.post") => {
+                        engine.on_post(serde_json::from_value(value).unwrap())
+                    }
+                    Some("app.bsky.feed.like") => {
+                        engine.on_like(serde_json::from_value(value).unwrap())
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    #[rstest]
+    fn replays_jetstream_capture(clock: ManualClock) {
+        let engine = engine(
+            r#"
+            half_life = "10m"
+            rule = { all = [{ min_score = 3.0 }, { langs = ["en"] }] }
+            "#,
+            &clock,
+        );
+        let decisions = replay(&engine, &clock);
+        let forwarded: Vec<&str> = decisions
+            .iter()
+            .filter_map(|decision| match decision {
+                Decision::Forward(post) => Some(post.uri.as_str()),
+                _ => None,
+            })
+            .collect();
+        let deleted: Vec<&str> = decisions
+            .iter()
+            .filter_map(|decision| match decision {
+                Decision::Delete(deleted) => Some(deleted.uri.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            forwarded,
+            vec!["at://did:plc:hot/app.bsky.feed.post/3m2y6a5h6os21"]
+        );
+        assert_eq!(
+            deleted,
+            vec!["at://did:plc:deleted/app.bsky.feed.post/3m2y6a5h6os24"]
+        );
+        // Liked as often as the forwarded post, but too slowly or not in english
+        let mut tracked: Vec<String> = engine
+            .tracked()
+            .into_iter()
+            .map(|tracked| tracked.post.uri)
+            .collect();
+        tracked.sort();
+        assert_eq!(
+            tracked,
+            vec![
+                "at://did:plc:german/app.bsky.feed.post/3m2y6a5h6os23",
+                "at://did:plc:slow/app.bsky.feed.post/3m2y6a5h6os22",
+            ]
+        );
+    }
+}
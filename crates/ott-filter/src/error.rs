@@ -0,0 +1,85 @@
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+/// Why a consumed record was skipped
+#[derive(Debug)]
+pub enum FilterError {
+    /// Not a commit, like jetstream account and identity events
+    NotCommit(String),
+    /// Not JSON, or not shaped like a post or like
+    Malformed(serde_json::Error),
+}
+
+impl FilterError {
+    /// Label the skipped records are counted by
+    pub fn reason(&self) -> &'static str {
+        match self {
+            FilterError::NotCommit(_) => "not_commit",
+            FilterError::Malformed(_) => "malformed",
+        }
+    }
+
+    /// Records that should have been handled, kept in the dead letter topic.
+    /// Other events are expected on the firehose and only counted
+    pub fn is_poison(&self) -> bool {
+        matches!(self, FilterError::Malformed(_))
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::NotCommit(kind) => write!(f, "{} event is not a commit", kind),
+            FilterError::Malformed(e) => write!(f, "malformed record: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+#[derive(Deserialize)]
+struct Event {
+    kind: Option<String>,
+}
+
+/// A post or like from its jetstream record
+pub fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T, FilterError> {
+    serde_json::from_slice(value).map_err(|e| match serde_json::from_slice::<Event>(value) {
+        Ok(Event { kind: Some(kind) }) if kind != "commit" => FilterError::NotCommit(kind),
+        _ => FilterError::Malformed(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ott_types::{Like, RawPost};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        r#"{"did":"did:plc:a","time_us":1,"kind":"identity","identity":{"did":"did:plc:a","seq":1}}"#,
+        "not_commit"
+    )]
+    #[case(
+        r#"{"did":"did:plc:a","time_us":1,"kind":"account","account":{"active":false}}"#,
+        "not_commit"
+    )]
+    #[case(r#"{"did":"did:plc:a","kind":"commit","commit":{}}"#, "malformed")]
+    #[case("not json", "malformed")]
+    fn skips_what_is_not_a_post(#[case] value: &str, #[case] reason: &str) {
+        let error = decode::<RawPost>(value.as_bytes()).unwrap_err();
+        assert_eq!(error.reason(), reason);
+        assert_eq!(error.is_poison(), reason == "malformed");
+    }
+
+    #[rstest]
+    fn decodes_likes() {
+        let like: Like = decode(
+            br#"{"did":"did:plc:a","time_us":1,"kind":"commit","uri":"at://did:plc:b/app.bsky.feed.post/1"}"#,
+        )
+        .unwrap();
+        assert_eq!(like.uri, "at://did:plc:b/app.bsky.feed.post/1");
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use ott_types::{Post, Record};
+use serde::Deserialize;
+
+/// Posts of an AppView, for the likes of feed users on posts the filter
+/// never saw or no longer tracks
+pub struct PostFetcher {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct GetPostsOutput {
+    posts: Vec<PostView>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PostView {
+    uri: String,
+    author: Author,
+    record: Record,
+    #[serde(default)]
+    like_count: u32,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    did: String,
+}
+
+impl PostFetcher {
+    /// Client of the AppView at `base_url`, like `https://public.api.bsky.app`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to create HTTP client"),
+            url: format!(
+                "{}/xrpc/app.bsky.feed.getPosts",
+                base_url.trim_end_matches('/')
+            ),
+        }
+    }
+
+    /// The post at `uri` with its likes so far, none when it was deleted
+    pub async fn get_post(&self, uri: &str) -> Result<Option<Post>> {
+        let response = self
+            .client
+            .get(&self.url)
+            .query(&[("uris", uri)])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("AppView answered {} for {}", response.status(), uri);
+        }
+        let output: GetPostsOutput = response.json().await?;
+        Ok(output.posts.into_iter().next().map(|view| Post {
+            did: view.author.did,
+            uri: view.uri,
+            text: view.record.text,
+            count: view.like_count,
+            scored_at_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_micros() as u64,
+            langs: view.record.langs.unwrap_or_default(),
+            reply: view.record.reply.is_some(),
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::Query, routing::get, Json, Router};
+    use rstest::rstest;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    const URI: &str = "at://did:plc:someone/app.bsky.feed.post/3m2y6a5h6os27";
+
+    /// An AppView knowing only the post at [`URI`]
+    async fn mock_appview() -> String {
+        let app = Router::new().route(
+            "/xrpc/app.bsky.feed.getPosts",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                let posts = if query.get("uris").map(String::as_str) == Some(URI) {
+                    vec![json!({
+                        "uri": URI,
+                        "cid": "bafyreid27zk7lbis4zw5fz4podbvbs4fc5ivwji3dmrwa6zggnj4bnd57u",
+                        "author": { "did": "did:plc:someone", "handle": "someone.bsky.social" },
+                        "record": {
+                            "$type": "app.bsky.feed.post",
+                            "text": "hello world",
+                            "langs": ["en"],
+                            "createdAt": "2025-10-01T11:00:00.000Z",
+                        },
+                        "likeCount": 3,
+                        "indexedAt": "2025-10-01T11:00:00.000Z",
+                    })]
+                } else {
+                    vec![]
+                };
+                Json(json!({ "posts": posts }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn fetches_liked_posts() {
+        let fetcher = PostFetcher::new(&mock_appview().await);
+        let post = fetcher.get_post(URI).await.unwrap().unwrap();
+        assert_eq!(post.did, "did:plc:someone");
+        assert_eq!(post.text, "hello world");
+        assert_eq!(post.langs, vec!["en".to_string()]);
+        assert_eq!(post.count, 3);
+        assert!(!post.reply);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn deleted_posts_are_none() {
+        let fetcher = PostFetcher::new(&mock_appview().await);
+        let uri = "at://did:plc:someone/app.bsky.feed.post/deleted";
+        assert!(fetcher.get_post(uri).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,12 @@
+pub mod engine;
+pub mod error;
+pub mod fetch;
+pub mod rules;
+pub mod snapshot;
+pub mod velocity;
+
+pub use engine::{Clock, Decision, FilterEngine, SystemClock, Tracked};
+pub use error::{decode, FilterError};
+pub use fetch::PostFetcher;
+pub use rules::FilterConfig;
+pub use snapshot::Snapshot;
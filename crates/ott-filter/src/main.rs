@@ -1,26 +1,34 @@
-use serde::{Deserialize, Serialize};
-use tokio::{
-    select,
-    sync::mpsc::{self, Receiver, Sender},
-    time::Duration,
-};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use tokio::{select, time::interval};
 
 use tokio_stream::StreamExt;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
+use anyhow::{bail, Context};
 use fluvio::{
-    consumer::{ConsumerConfigExtBuilder, ConsumerStream},
+    consumer::{ConsumerConfigExtBuilder, ConsumerStream, Record},
     metadata::topic::TopicSpec,
-    Fluvio, Offset,
+    Fluvio, Offset, RecordKey, TopicProducerPool,
+};
+use ott_filter::{
+    decode, rules::Topics, Decision, FilterConfig, FilterEngine, FilterError, PostFetcher,
+    Snapshot, SystemClock,
 };
-use moka::{ops::compute::Op, sync::Cache};
-use ott_types::{Commit, Like, Post, RawPost};
+use ott_stream::{
+    committed, partition_count, Assignment, ConsumerOptions, DeadLetters, TopicConsumer,
+};
+use ott_types::{DeadLetter, Like, RawPost, VipUser};
+use serde::Serialize;
+
+/// The VIP topic is small and read completely by every replica
+const VIP_PARTITION: u32 = 0;
+const CONSUMER_GROUP: &str = "ott-filter";
 
-const LIKES_TOPIC: &str = "raw-likes";
-const RAW_POSTS_TOPIC: &str = "raw-posts";
-const POSTS_TOPIC: &str = "posts";
-const PARTITION_NUM: u32 = 0;
+/// How often the produced records are flushed and the consumed offsets committed
+const COMMIT_INTERVAL: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() {
@@ -29,102 +37,257 @@ async fn main() {
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let posts_cache: Cache<String, Post> = Cache::builder()
-        .time_to_live(Duration::from_secs(60 * 60))
-        .build();
+    let config = match std::env::var_os("FILTER_RULES") {
+        Some(path) => FilterConfig::load(path.as_ref()).expect("Failed to load filter rules"),
+        None => FilterConfig::default(),
+    };
+    info!("Forwarding posts matching {:?}", config.rule);
+    let topics = config.topics.clone();
+    let consumer_options =
+        ConsumerOptions::from_env(CONSUMER_GROUP).expect("Invalid consumer options");
+    let assignment = Assignment::from_env().expect("Invalid replica assignment");
+
+    let mut engine = FilterEngine::new(&config, SystemClock);
 
     let fluvio = Fluvio::connect()
         .await
         .expect("Failed to connect to Fluvio");
 
-    // Create a topic
-    let admin = fluvio.admin().await;
-    let topics = admin
-        .all::<TopicSpec>()
-        .await
-        .expect("Failed to list topics")
-        .iter()
-        .map(|topic| topic.name.clone())
-        .collect::<Vec<String>>();
-
-    if !topics.contains(&POSTS_TOPIC.to_string()) {
-        warn!("Creating posts topic");
-        let topic_spec = TopicSpec::new_computed(1, 1, None);
-        admin
-            .create(POSTS_TOPIC.to_string(), false, topic_spec)
+    // Counts carry on from the snapshot and the streams continue right after
+    // the records it includes, unless a new group was told to start elsewhere
+    let starts_over = consumer_options.start_from.is_some()
+        && committed(&fluvio, &consumer_options.group, &topics.raw_posts)
             .await
-            .unwrap();
+            .expect("Failed to get committed offsets")
+            .is_empty();
+    let mut offsets: BTreeMap<String, BTreeMap<u32, i64>> = BTreeMap::new();
+    if let Some(path) = &config.snapshot
+        && !starts_over
+    {
+        match Snapshot::read(path) {
+            Ok(Some(snapshot)) => {
+                let restored = engine.restore(snapshot.posts);
+                info!("Restored {} posts from {}", restored, path.display());
+                offsets = snapshot.offsets;
+            }
+            Ok(None) => info!("No snapshot at {} yet", path.display()),
+            Err(e) => warn!("Ignoring snapshot {}: {}", path.display(), e),
+        }
+    }
+
+    if let Err(e) = create_topics(&fluvio, &topics).await {
+        error!("{:#}", e);
+        std::process::exit(1);
+    }
+    let partitions = match consumed_partitions(&fluvio, &topics).await {
+        Ok(partitions) => partitions,
+        Err(e) => {
+            error!("{:#}", e);
+            std::process::exit(1);
+        }
     };
+    let partitions = assignment.partitions(partitions);
+    info!(
+        "Replica {} of {} consuming partitions {:?}",
+        assignment.replica, assignment.replicas, partitions
+    );
 
-    let posts_fut = get_topic_stream(RAW_POSTS_TOPIC, PARTITION_NUM, &fluvio);
-    let like_fut = get_topic_stream(LIKES_TOPIC, PARTITION_NUM, &fluvio);
-    let (mut posts_stream, mut like_stream) = tokio::join!(posts_fut, like_fut);
+    let no_offsets = BTreeMap::new();
+    let posts_fut = TopicConsumer::resume(
+        &fluvio,
+        &topics.raw_posts,
+        &partitions,
+        &consumer_options,
+        offsets.get(&topics.raw_posts).unwrap_or(&no_offsets),
+    );
+    let like_fut = TopicConsumer::resume(
+        &fluvio,
+        &topics.likes,
+        &partitions,
+        &consumer_options,
+        offsets.get(&topics.likes).unwrap_or(&no_offsets),
+    );
+    let vip_fut = get_topic_stream(&topics.vip_users, VIP_PARTITION, &fluvio);
+    let (posts_stream, like_stream, mut vip_stream) = tokio::join!(posts_fut, like_fut, vip_fut);
+    let mut posts_stream = posts_stream.expect("Failed to create posts consumer");
+    let mut like_stream = like_stream.expect("Failed to create likes consumer");
 
-    let (embed_tx, embed_rx) = mpsc::channel::<Post>(1000);
+    let posts_producer = fluvio
+        .topic_producer(&topics.posts)
+        .await
+        .expect("Failed to create producer");
+    let deletes_producer = fluvio
+        .topic_producer(&topics.deletes)
+        .await
+        .expect("Failed to create producer");
+    let dead_letters = DeadLetters::connect(&fluvio, &topics.dead_letters)
+        .await
+        .expect("Failed to create dead letter producer");
 
-    // Start embedding tracing_subscriber
-    let fut = async move {
-        embed_post(embed_rx).await;
-    };
-    tokio::spawn(fut);
+    // Records skipped since the last commit, by reason
+    let mut skipped: BTreeMap<&'static str, u64> = BTreeMap::new();
+    // Records whose send failed, the offsets stay put until they are sent
+    let mut unsent: Vec<(&TopicProducerPool, String)> = Vec::new();
+    let fetcher = PostFetcher::new(&config.appview);
+    let mut commit_timer = interval(COMMIT_INTERVAL);
+    let mut snapshot_timer = interval(config.snapshot_interval);
 
     loop {
-        let pcc = posts_cache.clone();
-        let lcc = posts_cache.clone();
+        let mut decision = None;
         select! {
             Some(Ok(record)) = posts_stream.next() => {
-                let post: RawPost = serde_json::from_slice(record.value()).unwrap();
-                match &post.commit {
-                    Commit::Create{record} => {
-                        pcc.entry(post.uri.clone())
-                            .and_compute_with(|maybe_entry| {
-                                    if maybe_entry.is_some() {
-                                        Op::Nop
-                                    } else {
-                                        let post = Post{
-                                            uri: post.uri,
-                                            did: post.did,
-                                            text: record.text.to_string(),
-                                            ..Default::default()};
-                                        Op::Put(post) // Insert
-                                    }
-                            }
-                        );
+                match decode::<RawPost>(record.value()) {
+                    Ok(post) => decision = engine.on_post(post),
+                    Err(e) => skip(&mut skipped, &dead_letters, &topics.raw_posts, &record, e).await,
+                }
+            },
+            Some(Ok(record)) = like_stream.next() => {
+                match decode::<Like>(record.value()) {
+                    Ok(like) => decision = engine.on_like(like),
+                    Err(e) => skip(&mut skipped, &dead_letters, &topics.likes, &record, e).await,
+                }
+            },
+            // Read from the beginning on every start, so this is every user ever announced
+            Some(Ok(record)) = vip_stream.next() => {
+                match serde_json::from_slice::<VipUser>(record.value()) {
+                    Ok(user) => {
+                        if engine.add_vip(user.did.clone()) {
+                            info!("Forwarding all likes of {}", user.did);
+                        }
                     },
-                    _ => {
-                        info!("Got create or update post");
+                    Err(e) => warn!("Failed deserializing vip user: {}", e),
+                }
+            },
+            _ = commit_timer.tick() => {
+                if !skipped.is_empty() {
+                    warn!("Skipped records {:?}", skipped);
+                    skipped.clear();
+                }
+                // Offsets only move once the records produced so far are stored
+                if let Err(e) = resend(&mut unsent).await {
+                    error!("{}", e);
+                    continue;
+                }
+                if let Err(e) = flush(&posts_producer, &deletes_producer, &dead_letters).await {
+                    error!("{}", e);
+                    continue;
+                }
+                for stream in [&mut posts_stream, &mut like_stream] {
+                    if let Err(e) = stream.commit().await {
+                        error!("{}", e);
                     }
                 }
-
             },
-            Some(Ok(record)) = like_stream.next() => {
-                if let Ok(like) = serde_json::from_slice::<Like>(record.value()) {
-                    lcc.entry(like.uri)
-                        .and_compute_with(|maybe_entry| {
-                            if let Some(entry) = maybe_entry {
-                                let mut post = entry.into_value();
-                                if post.count < 20 {
-                                    post.count +=1;
-                                    Op::Put(post)
-                                } else {
-                                    let tx_clone = embed_tx.clone();
-                                    tokio::spawn(async move {
-                                        if let Err(e) = tx_clone.send(post).await {
-                                            error!("Failed to send post: {}", e);
-                                        }
-                                    });
-                                    Op::Remove
-                                }
-                            } else {
-                                Op::Nop // Skip as post is out of cache
-                            }
-                    });
-                 } else {
-                     warn!("Failed deserializing, likely not like commit");
+            _ = snapshot_timer.tick(), if config.snapshot.is_some() => {
+                // The posts forwarded so far are not forwarded again after a restore
+                if let Err(e) = resend(&mut unsent).await {
+                    error!("{}", e);
+                    continue;
+                }
+                if let Err(e) = flush(&posts_producer, &deletes_producer, &dead_letters).await {
+                    error!("{}", e);
+                    continue;
+                }
+                for (topic, stream) in [(&topics.raw_posts, &posts_stream), (&topics.likes, &like_stream)] {
+                    offsets
+                        .entry(topic.clone())
+                        .or_default()
+                        .extend(stream.offsets());
+                }
+                let snapshot = Snapshot {
+                    taken_at_us: engine.now_us(),
+                    offsets: offsets.clone(),
+                    posts: engine.tracked(),
                 };
+                let tracked = snapshot.posts.len();
+                let path = config.snapshot.clone().unwrap();
+                match tokio::task::spawn_blocking(move || snapshot.write(&path)).await {
+                    Ok(Ok(())) => debug!("Snapshot of {} posts", tracked),
+                    Ok(Err(e)) => error!("Failed to write snapshot: {}", e),
+                    Err(e) => error!("Failed to write snapshot: {}", e),
+                }
             }
         }
+
+        match decision {
+            Some(Decision::Forward(post)) => produce(&mut unsent, &posts_producer, &post).await,
+            Some(Decision::Delete(deleted)) => {
+                produce(&mut unsent, &deletes_producer, &deleted).await
+            }
+            Some(Decision::Fetch(uri)) => match fetcher.get_post(&uri).await {
+                Ok(Some(post)) => produce(&mut unsent, &posts_producer, &post).await,
+                Ok(None) => debug!("Liked post {} is gone", uri),
+                Err(e) => {
+                    warn!("Failed to fetch liked post {}: {}", uri, e);
+                    let letter = DeadLetter {
+                        topic: topics.likes.clone(),
+                        partition: None,
+                        offset: None,
+                        reason: "fetch".to_string(),
+                        error: e.to_string(),
+                        attempts: 1,
+                        value: uri,
+                    };
+                    dead_letters.send(&letter).await;
+                }
+            },
+            None => {}
+        }
+    }
+}
+
+/// Creates the topics the filter produces to when missing, the VIP topic
+/// with the single partition every replica reads
+async fn create_topics(fluvio: &Fluvio, topics: &Topics) -> anyhow::Result<()> {
+    let admin = fluvio.admin().await;
+    let existing = admin
+        .all::<TopicSpec>()
+        .await
+        .context("Failed to list topics")?
+        .into_iter()
+        .map(|topic| topic.name)
+        .collect::<Vec<String>>();
+
+    for (topic, partitions) in [
+        (&topics.posts, topics.partitions),
+        (&topics.deletes, topics.partitions),
+        (&topics.vip_users, 1),
+    ] {
+        if !existing.contains(topic) {
+            warn!("Creating {} topic with {} partitions", topic, partitions);
+            admin
+                .create(
+                    topic.clone(),
+                    false,
+                    TopicSpec::new_computed(partitions, 1, None),
+                )
+                .await
+                .with_context(|| format!("Failed to create {} topic", topic))?;
+        }
     }
+    Ok(())
+}
+
+/// Partitions of the posts and likes. Records are keyed by post uri, with as
+/// many partitions in both topics the likes of a post are in the partition
+/// of the same number as the post
+async fn consumed_partitions(fluvio: &Fluvio, topics: &Topics) -> anyhow::Result<u32> {
+    let partitions = partition_count(fluvio, &topics.raw_posts)
+        .await
+        .context("Failed to get posts partitions")?;
+    let like_partitions = partition_count(fluvio, &topics.likes)
+        .await
+        .context("Failed to get likes partitions")?;
+    if partitions != like_partitions {
+        bail!(
+            "{} has {} partitions and {} has {}, they need the same number",
+            topics.raw_posts,
+            partitions,
+            topics.likes,
+            like_partitions
+        );
+    }
+    Ok(partitions)
 }
 
 async fn get_topic_stream(topic: &str, partition: u32, fluvio: &Fluvio) -> impl ConsumerStream {
@@ -134,30 +297,76 @@ async fn get_topic_stream(topic: &str, partition: u32, fluvio: &Fluvio) -> impl
         .offset_start(Offset::beginning())
         .build()
         .expect("Failed to build consumer config");
-    let stream = fluvio
+    fluvio
         .consumer_with_config(config)
         .await
-        .expect("Failed to create consumer");
-    stream
+        .expect("Failed to create consumer")
 }
 
-struct PostEmbedding {
-    uri: String,
-    vector: Vec<f32>,
+/// Count a record that could not be handled, poison ones also go to the dead letters
+async fn skip(
+    skipped: &mut BTreeMap<&'static str, u64>,
+    dead_letters: &DeadLetters,
+    topic: &str,
+    record: &Record,
+    error: FilterError,
+) {
+    debug!(
+        "Skipping {}/{} at {}: {}",
+        topic,
+        record.partition(),
+        record.offset(),
+        error
+    );
+    *skipped.entry(error.reason()).or_default() += 1;
+    if error.is_poison() {
+        dead_letters
+            .send_record(topic, record, error.reason(), &error)
+            .await;
+    }
 }
 
-async fn embed_post(mut post_rx: Receiver<Post>) {
-    let producer = fluvio::producer(POSTS_TOPIC)
+async fn flush(
+    posts: &TopicProducerPool,
+    deletes: &TopicProducerPool,
+    dead_letters: &DeadLetters,
+) -> anyhow::Result<()> {
+    posts
+        .flush()
         .await
-        .expect("Failed to create producer");
+        .map_err(|e| anyhow::anyhow!("Failed to flush posts: {}", e))?;
+    deletes
+        .flush()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to flush deletes: {}", e))?;
+    dead_letters.flush().await
+}
 
-    while let Some(post) = post_rx.recv().await {
-        producer
-            .send(
-                fluvio::RecordKey::NULL,
-                serde_json::to_string(&post).unwrap(),
-            )
-            .await
-            .expect("Failed to send record");
+/// Failed sends are kept in `unsent`, to be sent again before the next commit
+async fn produce<'a, T: Serialize>(
+    unsent: &mut Vec<(&'a TopicProducerPool, String)>,
+    producer: &'a TopicProducerPool,
+    record: &T,
+) {
+    let value = serde_json::to_string(record).expect("Records serialize");
+    if let Err(e) = producer.send(RecordKey::NULL, value.clone()).await {
+        error!("Failed to send record: {}", e);
+        unsent.push((producer, value));
+    }
+}
+
+/// Send the records that failed before, those failing again are kept
+async fn resend(unsent: &mut Vec<(&TopicProducerPool, String)>) -> anyhow::Result<()> {
+    let mut failed = Vec::new();
+    for (producer, value) in unsent.drain(..) {
+        if producer.send(RecordKey::NULL, value.clone()).await.is_err() {
+            failed.push((producer, value));
+        }
+    }
+    let count = failed.len();
+    *unsent = failed;
+    if count > 0 {
+        anyhow::bail!("Failed to send {} records, not committing", count);
     }
+    Ok(())
 }
@@ -0,0 +1,290 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use ott_types::Post;
+use serde::Deserialize;
+
+/// What the filter reads, forwards and how, loaded from the file in
+/// `FILTER_RULES` (TOML, or YAML for `.yaml`/`.yml`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// How long a post is tracked for likes after it was seen
+    #[serde(default = "default_window", with = "humantime_serde")]
+    pub window: Duration,
+
+    /// Half life of likes in the like velocity score
+    #[serde(default = "default_half_life", with = "humantime_serde")]
+    pub half_life: Duration,
+
+    #[serde(default)]
+    pub topics: Topics,
+
+    /// File the tracked posts are snapshotted to and restored from on start
+    #[serde(default)]
+    pub snapshot: Option<PathBuf>,
+
+    #[serde(default = "default_snapshot_interval", with = "humantime_serde")]
+    pub snapshot_interval: Duration,
+
+    /// AppView the posts feed users like are fetched from when not tracked
+    #[serde(default = "default_appview")]
+    pub appview: String,
+
+    /// Posts matching the rule are forwarded to the embedder, checked when
+    /// the post is created and on each like
+    #[serde(default = "default_rule")]
+    pub rule: Rule,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Topics {
+    pub raw_posts: String,
+    pub likes: String,
+    pub posts: String,
+    /// Users of the feeds, their likes forward posts regardless of the rule
+    pub vip_users: String,
+    /// Deleted posts, removed from the vectors by the embedder
+    pub deletes: String,
+    /// Records that could not be handled, with the reason
+    pub dead_letters: String,
+    /// Partitions of the posts and deletes topics when the filter creates them
+    pub partitions: u32,
+}
+
+impl Default for Topics {
+    fn default() -> Self {
+        Self {
+            raw_posts: "raw-posts".to_string(),
+            likes: "raw-likes".to_string(),
+            posts: "posts".to_string(),
+            vip_users: "vip-users".to_string(),
+            deletes: "post-deletes".to_string(),
+            dead_letters: "filter-dlq".to_string(),
+            partitions: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Rule {
+    /// Every rule matches, true when empty
+    All(Vec<Rule>),
+    /// At least one rule matches, false when empty
+    Any(Vec<Rule>),
+    Not(Box<Rule>),
+    /// Liked at least this often within the window
+    MinLikes(u32),
+    /// Like velocity of at least this, likes decayed by their age
+    MinScore(f64),
+    /// Written in one of the languages, `en` also matches `en-US`
+    Langs(Vec<String>),
+    /// At least this many characters of text
+    MinTextLen(usize),
+    /// A reply when true, a top level post when false
+    Reply(bool),
+    /// Posted by one of the DIDs, wrap in `not` for a deny list
+    Authors(Vec<String>),
+}
+
+impl Rule {
+    pub fn matches(&self, post: &Post) -> bool {
+        match self {
+            Rule::All(rules) => rules.iter().all(|rule| rule.matches(post)),
+            Rule::Any(rules) => rules.iter().any(|rule| rule.matches(post)),
+            Rule::Not(rule) => !rule.matches(post),
+            Rule::MinLikes(likes) => post.count >= *likes,
+            Rule::MinScore(score) => post.score >= *score,
+            Rule::Langs(langs) => post.langs.iter().any(|lang| {
+                langs.iter().any(|allowed| {
+                    lang.eq_ignore_ascii_case(allowed)
+                        || lang
+                            .to_ascii_lowercase()
+                            .starts_with(&format!("{}-", allowed.to_ascii_lowercase()))
+                })
+            }),
+            Rule::MinTextLen(len) => post.text.chars().count() >= *len,
+            Rule::Reply(reply) => post.reply == *reply,
+            Rule::Authors(dids) => dids.contains(&post.did),
+        }
+    }
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+fn default_half_life() -> Duration {
+    Duration::from_secs(10 * 60)
+}
+
+fn default_snapshot_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_appview() -> String {
+    "https://public.api.bsky.app".to_string()
+}
+
+fn default_rule() -> Rule {
+    Rule::MinScore(10.0)
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            window: default_window(),
+            half_life: default_half_life(),
+            topics: Topics::default(),
+            snapshot: None,
+            snapshot_interval: default_snapshot_interval(),
+            appview: default_appview(),
+            rule: default_rule(),
+        }
+    }
+}
+
+impl FilterConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rules {}", path.display()))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&content),
+            _ => Self::from_toml(&content),
+        }
+        .with_context(|| format!("Invalid rules {}", path.display()))
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// serde_yaml wants `!tags` for enums, going through json allows the
+    /// same single key maps as in TOML
+    pub fn from_yaml(content: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_yaml::from_str(content)?;
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+
+    const TOML: &str = r#"
+        window = "30m"
+        half_life = "5m"
+        snapshot = "/data/filter.snapshot"
+
+        [topics]
+        posts = "english-posts"
+
+        [rule]
+        all = [
+            { min_likes = 10 },
+            { min_score = 2.5 },
+            { langs = ["en"] },
+            { min_text_len = 5 },
+            { reply = false },
+            { any = [
+                { authors = ["did:plc:friend"] },
+                { not = { authors = ["did:plc:spam"] } },
+            ] },
+        ]
+    "#;
+
+    const YAML: &str = r#"
+        window: 30m
+        half_life: 5m
+        snapshot: /data/filter.snapshot
+        topics:
+          posts: english-posts
+        rule:
+          all:
+            - min_likes: 10
+            - min_score: 2.5
+            - langs: [en]
+            - min_text_len: 5
+            - reply: false
+            - any:
+                - authors: ["did:plc:friend"]
+                - not:
+                    authors: ["did:plc:spam"]
+    "#;
+
+    #[fixture]
+    fn post() -> Post {
+        Post {
+            did: "did:plc:someone".to_string(),
+            uri: "at://did:plc:someone/app.bsky.feed.post/3m2y6a5h6os27".to_string(),
+            text: "hello world".to_string(),
+            count: 10,
+            score: 3.0,
+            scored_at_us: 0,
+            langs: vec!["en-US".to_string()],
+            reply: false,
+            attempts: 0,
+        }
+    }
+
+    #[rstest]
+    fn toml_and_yaml_agree() {
+        let toml = FilterConfig::from_toml(TOML).unwrap();
+        let yaml = FilterConfig::from_yaml(YAML).unwrap();
+        assert_eq!(toml.window, Duration::from_secs(30 * 60));
+        assert_eq!(toml.topics.posts, "english-posts");
+        assert_eq!(toml.topics.likes, "raw-likes");
+        assert_eq!(toml.topics.vip_users, "vip-users");
+        assert_eq!(toml.topics.deletes, "post-deletes");
+        assert_eq!(toml.topics.dead_letters, "filter-dlq");
+        assert_eq!(toml.topics.partitions, 1);
+        assert_eq!(toml.rule, yaml.rule);
+        assert_eq!(toml.window, yaml.window);
+        assert_eq!(toml.half_life, Duration::from_secs(5 * 60));
+        assert_eq!(toml.snapshot, yaml.snapshot);
+        assert_eq!(toml.snapshot_interval, Duration::from_secs(60));
+    }
+
+    #[rstest]
+    fn defaults_forward_hot_posts() {
+        let config = FilterConfig::from_toml("").unwrap();
+        assert_eq!(config.window, Duration::from_secs(60 * 60));
+        assert_eq!(config.half_life, Duration::from_secs(10 * 60));
+        assert!(config.snapshot.is_none());
+        assert_eq!(config.appview, "https://public.api.bsky.app");
+        assert_eq!(config.rule, Rule::MinScore(10.0));
+    }
+
+    #[rstest]
+    fn unknown_rule_is_rejected() {
+        assert!(FilterConfig::from_toml("[rule]\nmin_reposts = 3").is_err());
+    }
+
+    #[rstest]
+    #[case::matching(|_: &mut Post| {}, true)]
+    #[case::too_few_likes(|p: &mut Post| p.count = 9, false)]
+    #[case::too_slow(|p: &mut Post| p.score = 2.0, false)]
+    #[case::other_language(|p: &mut Post| p.langs = vec!["de".to_string()], false)]
+    #[case::no_language(|p: &mut Post| p.langs = vec![], false)]
+    #[case::too_short(|p: &mut Post| p.text = "hi".to_string(), false)]
+    #[case::reply(|p: &mut Post| p.reply = true, false)]
+    #[case::denied_author(|p: &mut Post| p.did = "did:plc:spam".to_string(), false)]
+    #[case::allowed_author(|p: &mut Post| p.did = "did:plc:friend".to_string(), true)]
+    fn rule_matches(post: Post, #[case] edit: fn(&mut Post), #[case] expected: bool) {
+        let rule = FilterConfig::from_toml(TOML).unwrap().rule;
+        let mut post = post;
+        edit(&mut post);
+        assert_eq!(rule.matches(&post), expected);
+    }
+
+    #[rstest]
+    #[case(Rule::All(vec![]), true)]
+    #[case(Rule::Any(vec![]), false)]
+    fn empty_combinators(post: Post, #[case] rule: Rule, #[case] expected: bool) {
+        assert_eq!(rule.matches(&post), expected);
+    }
+}
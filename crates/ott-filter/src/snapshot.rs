@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Tracked;
+
+const MAGIC: &[u8; 4] = b"OTTF";
+/// Bumped whenever the layout of `Snapshot` changes, older snapshots are
+/// then ignored instead of misread
+const VERSION: u32 = 3;
+
+/// The tracked posts along with the offsets of the last records they
+/// include, so a restart continues counting right after them.
+///
+/// Written as `OTTF`, the version as little endian u32 and the snapshot as CBOR.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Snapshot {
+    /// Time the snapshot was taken, unix micros
+    pub taken_at_us: u64,
+    /// Offset of the last record handled, by topic and partition
+    pub offsets: BTreeMap<String, BTreeMap<u32, i64>>,
+    pub posts: Vec<Tracked>,
+}
+
+impl Snapshot {
+    /// Replaces the file at `path` only once the snapshot is completely written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        ciborium::into_writer(self, &mut file)?;
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// The snapshot at `path`, none when there is no snapshot yet
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut file = std::io::BufReader::new(file);
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)
+            .context("Snapshot is truncated")?;
+        if &header[..4] != MAGIC {
+            bail!("Not a filter snapshot");
+        }
+        let version = u32::from_le_bytes(header[4..].try_into()?);
+        if version != VERSION {
+            bail!("Snapshot version {} is not {}", version, VERSION);
+        }
+        Ok(Some(ciborium::from_reader(file)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ott_types::Post;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            taken_at_us: 1_759_320_000_000_000,
+            offsets: BTreeMap::from([
+                ("raw-posts".to_string(), BTreeMap::from([(0, 41), (2, 43)])),
+                ("raw-likes".to_string(), BTreeMap::from([(0, 1337)])),
+            ]),
+            posts: vec![Tracked {
+                seen_at_us: 1_759_318_000_000_000,
+                post: Post {
+                    did: "did:plc:someone".to_string(),
+                    uri: "at://did:plc:someone/app.bsky.feed.post/3m2y6a5h6os27".to_string(),
+                    text: "hello world".to_string(),
+                    count: 3,
+                    score: 2.5,
+                    scored_at_us: 1_759_319_000_000_000,
+                    langs: vec!["en".to_string()],
+                    reply: false,
+                    attempts: 0,
+                },
+            }],
+        }
+    }
+
+    #[rstest]
+    fn survives_restart(snapshot: Snapshot) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.snapshot");
+        assert!(Snapshot::read(&path).unwrap().is_none());
+
+        snapshot.write(&path).unwrap();
+        assert_eq!(Snapshot::read(&path).unwrap(), Some(snapshot));
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[rstest]
+    fn other_version_is_rejected(snapshot: Snapshot) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("filter.snapshot");
+        snapshot.write(&path).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        assert!(Snapshot::read(&path).is_err());
+    }
+}
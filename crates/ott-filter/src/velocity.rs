@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use ott_types::Post;
+
+/// `score` as of `from_us`, decayed to `to_us`. Halves every `half_life`
+pub fn decay(score: f64, from_us: u64, to_us: u64, half_life: Duration) -> f64 {
+    let elapsed = to_us.saturating_sub(from_us) as f64 / 1_000_000.0;
+    score * 0.5f64.powf(elapsed / half_life.as_secs_f64())
+}
+
+/// Count a like at `time_us` in the like velocity of `post`.
+///
+/// The score is the sum of the likes, each decayed by its age, so 20 likes
+/// within two minutes score close to 20 while 20 likes spread over an
+/// hour never get near it. Likes arriving out of order are decayed to the
+/// time of the score instead of rewinding it.
+pub fn add_like(post: &mut Post, time_us: u64, half_life: Duration) {
+    post.count += 1;
+    if time_us >= post.scored_at_us {
+        post.score = decay(post.score, post.scored_at_us, time_us, half_life) + 1.0;
+        post.scored_at_us = time_us;
+    } else {
+        post.score += decay(1.0, time_us, post.scored_at_us, half_life);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const HALF_LIFE: Duration = Duration::from_secs(10 * 60);
+    const MINUTE_US: u64 = 60 * 1_000_000;
+
+    fn liked_over(likes: u64, minutes: u64) -> Post {
+        let mut post = Post::default();
+        for like in 0..likes {
+            add_like(&mut post, like * minutes * MINUTE_US / likes, HALF_LIFE);
+        }
+        post
+    }
+
+    #[rstest]
+    fn fast_likes_score_higher() {
+        let fast = liked_over(20, 2);
+        let slow = liked_over(20, 59);
+        assert_eq!(fast.count, slow.count);
+        assert!(fast.score > 18.0, "{}", fast.score);
+        assert!(slow.score < 6.0, "{}", slow.score);
+    }
+
+    #[rstest]
+    #[case(0, 1.0)]
+    #[case(10 * MINUTE_US, 0.5)]
+    #[case(20 * MINUTE_US, 0.25)]
+    fn halves_every_half_life(#[case] elapsed: u64, #[case] expected: f64) {
+        assert!((decay(1.0, 0, elapsed, HALF_LIFE) - expected).abs() < 1e-9);
+    }
+
+    #[rstest]
+    fn late_likes_count_decayed() {
+        let mut post = Post::default();
+        add_like(&mut post, 10 * MINUTE_US, HALF_LIFE);
+        add_like(&mut post, 0, HALF_LIFE);
+        assert_eq!(post.scored_at_us, 10 * MINUTE_US);
+        assert!((post.score - 1.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
+use fluvio::{consumer::Record, Fluvio, RecordKey, TopicProducerPool};
+use ott_types::DeadLetter;
+use tracing::error;
+
+use crate::ensure_topic;
+
+/// Producer of the dead letter topic of a service
+pub struct DeadLetters {
+    topic: String,
+    producer: TopicProducerPool,
+}
+
+impl DeadLetters {
+    /// Creates the topic when missing
+    pub async fn connect(fluvio: &Fluvio, topic: &str) -> Result<Self> {
+        ensure_topic(fluvio, topic).await?;
+        Ok(Self {
+            topic: topic.to_string(),
+            producer: fluvio.topic_producer(topic).await?,
+        })
+    }
+
+    /// Publish `record` of `topic` along with why it could not be handled
+    /// on the first attempt
+    pub async fn send_record(
+        &self,
+        topic: &str,
+        record: &Record,
+        reason: &str,
+        error: impl Display,
+    ) {
+        let letter = DeadLetter {
+            topic: topic.to_string(),
+            partition: Some(record.partition()),
+            offset: Some(record.offset()),
+            reason: reason.to_string(),
+            error: error.to_string(),
+            attempts: 1,
+            value: String::from_utf8_lossy(record.value()).into_owned(),
+        };
+        self.send(&letter).await;
+    }
+
+    /// Only logged when this fails too, a dead letter never stops a service
+    pub async fn send(&self, letter: &DeadLetter) {
+        let value = serde_json::to_string(letter).expect("Dead letters serialize");
+        if let Err(e) = self.producer.send(RecordKey::NULL, value).await {
+            error!(
+                "Failed to send {} dead letter of {} to {}: {}",
+                letter.reason, letter.topic, self.topic, e
+            );
+        }
+    }
+
+    /// Dead letters sent so far are stored once this returns
+    pub async fn flush(&self) -> Result<()> {
+        self.producer
+            .flush()
+            .await
+            .map_err(|e| anyhow!("Failed to flush {}: {}", self.topic, e))
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use fluvio::{
+    consumer::{ConsumerConfigExtBuilder, ConsumerStream, OffsetManagementStrategy, Record},
+    metadata::topic::TopicSpec,
+    Fluvio, Offset,
+};
+use tokio_stream::{StreamExt, StreamMap};
+use tracing::{info, warn};
+
+mod dead_letter;
+pub use dead_letter::DeadLetters;
+
+/// Where a consumer group starts reading a partition it never committed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartFrom {
+    Beginning,
+    End,
+    /// First record produced at or after the time, found by reading from
+    /// the beginning, fluvio has no offsets by time
+    Timestamp(SystemTime),
+    /// Record at the absolute offset
+    Offset(i64),
+}
+
+impl FromStr for StartFrom {
+    type Err = anyhow::Error;
+
+    /// `beginning`, `end`, an offset or an RFC 3339 timestamp like `2025-10-01T12:00:00Z`
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "beginning" => Ok(StartFrom::Beginning),
+            "end" => Ok(StartFrom::End),
+            _ if value.bytes().all(|b| b.is_ascii_digit()) => Ok(StartFrom::Offset(value.parse()?)),
+            _ => humantime::parse_rfc3339_weak(value)
+                .map(StartFrom::Timestamp)
+                .map_err(|e| anyhow!("Invalid start {}, {}", value, e)),
+        }
+    }
+}
+
+impl fmt::Display for StartFrom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartFrom::Beginning => f.write_str("beginning"),
+            StartFrom::End => f.write_str("end"),
+            StartFrom::Timestamp(time) => write!(f, "{}", humantime::format_rfc3339(*time)),
+            StartFrom::Offset(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+/// How a service consumes its topics, from `CONSUMER_GROUP` and `START_FROM`
+#[derive(Debug, Clone)]
+pub struct ConsumerOptions {
+    /// Offsets are committed per group, a new group starts at the beginning
+    pub group: String,
+    /// Where partitions the group never committed start, instead of the
+    /// beginning. Committed partitions resume, so it is applied once per group
+    pub start_from: Option<StartFrom>,
+}
+
+impl ConsumerOptions {
+    pub fn from_env(default_group: &str) -> Result<Self> {
+        let group = std::env::var("CONSUMER_GROUP").unwrap_or_else(|_| default_group.to_string());
+        let start_from = std::env::var("START_FROM")
+            .ok()
+            .map(|value| value.parse())
+            .transpose()?;
+        Ok(Self { group, start_from })
+    }
+}
+
+/// Which partitions of the co-partitioned input topics a replica consumes,
+/// partition `p` goes to replica `p % replicas`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Assignment {
+    pub replica: u32,
+    pub replicas: u32,
+}
+
+impl Assignment {
+    /// From `REPLICAS` and `REPLICA`, or the ordinal suffix of `POD_NAME` as
+    /// in a StatefulSet, a single replica consuming everything otherwise
+    pub fn from_env() -> Result<Self> {
+        let replicas = match std::env::var("REPLICAS") {
+            Ok(replicas) => replicas.parse()?,
+            Err(_) => 1,
+        };
+        let replica = match (std::env::var("REPLICA"), std::env::var("POD_NAME")) {
+            (Ok(replica), _) => replica.parse()?,
+            (_, Ok(pod)) => ordinal(&pod)?,
+            _ => 0,
+        };
+        Self::new(replica, replicas)
+    }
+
+    pub fn new(replica: u32, replicas: u32) -> Result<Self> {
+        if replica >= replicas {
+            bail!("Replica {} out of {} replicas", replica, replicas);
+        }
+        Ok(Self { replica, replicas })
+    }
+
+    /// The partitions of a topic with `count` partitions this replica consumes
+    pub fn partitions(&self, count: u32) -> Vec<u32> {
+        (0..count)
+            .filter(|partition| partition % self.replicas == self.replica)
+            .collect()
+    }
+}
+
+impl Default for Assignment {
+    fn default() -> Self {
+        Self {
+            replica: 0,
+            replicas: 1,
+        }
+    }
+}
+
+/// Ordinal of a StatefulSet pod, `ott-filter-2` is 2
+fn ordinal(pod: &str) -> Result<u32> {
+    pod.rsplit_once('-')
+        .and_then(|(_, ordinal)| ordinal.parse().ok())
+        .ok_or_else(|| anyhow!("No ordinal in pod name {}", pod))
+}
+
+/// Creates `topic` with a single partition when missing
+pub async fn ensure_topic(fluvio: &Fluvio, topic: &str) -> Result<()> {
+    let admin = fluvio.admin().await;
+    let exists = !admin
+        .list::<TopicSpec, _>(vec![topic.to_string()])
+        .await?
+        .is_empty();
+    if !exists {
+        warn!("Creating {} topic", topic);
+        admin
+            .create(
+                topic.to_string(),
+                false,
+                TopicSpec::new_computed(1, 1, None),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Number of partitions of `topic`
+pub async fn partition_count(fluvio: &Fluvio, topic: &str) -> Result<u32> {
+    fluvio
+        .admin()
+        .await
+        .list::<TopicSpec, _>(vec![topic.to_string()])
+        .await?
+        .first()
+        .map(|topic| topic.spec.partitions())
+        .ok_or_else(|| anyhow!("No topic {}", topic))
+}
+
+/// Consumer of some partitions of a topic that only moves the committed
+/// offsets of its group when told to, after the records read so far were handled
+pub struct TopicConsumer {
+    streams: StreamMap<u32, Box<dyn ConsumerStream + Send>>,
+    /// Records produced before this, unix millis, are skipped, by partition
+    /// starting from a timestamp
+    not_before: BTreeMap<u32, i64>,
+    /// Offset of the last record returned, by partition
+    offsets: BTreeMap<u32, i64>,
+}
+
+impl TopicConsumer {
+    pub async fn connect(
+        fluvio: &Fluvio,
+        topic: &str,
+        partitions: &[u32],
+        options: &ConsumerOptions,
+    ) -> Result<Self> {
+        Self::resume(fluvio, topic, partitions, options, &BTreeMap::new()).await
+    }
+
+    /// Like `connect`, but the partitions in `after` continue right after
+    /// the given offset instead of the committed one
+    pub async fn resume(
+        fluvio: &Fluvio,
+        topic: &str,
+        partitions: &[u32],
+        options: &ConsumerOptions,
+        after: &BTreeMap<u32, i64>,
+    ) -> Result<Self> {
+        let committed = committed(fluvio, &options.group, topic).await?;
+        let mut streams = StreamMap::new();
+        let mut not_before = BTreeMap::new();
+        for &partition in partitions {
+            let start_from = match after.get(&partition) {
+                Some(offset) => Some(StartFrom::Offset(offset + 1)),
+                None if committed.contains(&partition) => {
+                    if let Some(start_from) = options.start_from {
+                        info!(
+                            "Resuming {}/{} as {}, {} only applies to partitions never committed",
+                            topic, partition, options.group, start_from
+                        );
+                    }
+                    None
+                }
+                None => options.start_from,
+            };
+            if let Some(StartFrom::Timestamp(time)) = start_from {
+                not_before.insert(
+                    partition,
+                    time.duration_since(UNIX_EPOCH)?.as_millis() as i64,
+                );
+            }
+            let stream =
+                partition_stream(fluvio, topic, partition, &options.group, start_from).await?;
+            streams.insert(partition, stream);
+        }
+        Ok(Self {
+            streams,
+            not_before,
+            offsets: BTreeMap::new(),
+        })
+    }
+
+    /// Next record of any of the partitions, `None` when the streams ended
+    pub async fn next(&mut self) -> Option<Result<Record>> {
+        loop {
+            let record = match self.streams.next().await? {
+                (_, Ok(record)) => record,
+                (partition, Err(e)) => {
+                    return Some(Err(anyhow!(
+                        "Failed to consume partition {}: {}",
+                        partition,
+                        e
+                    )));
+                }
+            };
+            if before(&self.not_before, record.partition(), record.timestamp()) {
+                continue;
+            }
+            self.offsets.insert(record.partition(), record.offset());
+            return Some(Ok(record));
+        }
+    }
+
+    /// Offsets of the last records returned by `next`, by partition
+    pub fn offsets(&self) -> &BTreeMap<u32, i64> {
+        &self.offsets
+    }
+
+    /// Commit everything returned by `next` so far, a restart resumes after it
+    pub async fn commit(&mut self) -> Result<()> {
+        for (partition, stream) in self.streams.iter_mut() {
+            stream
+                .offset_commit()
+                .await
+                .map_err(|e| anyhow!("Failed to commit offset of {}: {}", partition, e))?;
+            stream
+                .offset_flush()
+                .await
+                .map_err(|e| anyhow!("Failed to flush offset of {}: {}", partition, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a record of `partition` produced at `timestamp` comes before the
+/// time its partition starts from. Records without a timestamp are kept
+fn before(not_before: &BTreeMap<u32, i64>, partition: u32, timestamp: i64) -> bool {
+    match not_before.get(&partition) {
+        Some(&not_before) => timestamp >= 0 && timestamp < not_before,
+        None => false,
+    }
+}
+
+/// Where a partition starts. Fluvio resolves the beginning and the end from
+/// the committed offset when there is one, but not absolute offsets
+fn offset_start(start_from: Option<StartFrom>) -> Result<Offset> {
+    Ok(match start_from {
+        Some(StartFrom::End) => Offset::end(),
+        Some(StartFrom::Offset(offset)) => Offset::absolute(offset)?,
+        // Timestamps read from the beginning, the records before are skipped
+        Some(StartFrom::Beginning | StartFrom::Timestamp(_)) | None => Offset::beginning(),
+    })
+}
+
+async fn partition_stream(
+    fluvio: &Fluvio,
+    topic: &str,
+    partition: u32,
+    group: &str,
+    start_from: Option<StartFrom>,
+) -> Result<Box<dyn ConsumerStream + Send>> {
+    if let Some(start_from) = start_from {
+        info!(
+            "Consuming {}/{} as {} from {}",
+            topic, partition, group, start_from
+        );
+    }
+    let config = ConsumerConfigExtBuilder::default()
+        .topic(topic)
+        .partition(partition)
+        .offset_consumer(group.to_string())
+        .offset_start(offset_start(start_from)?)
+        .offset_strategy(OffsetManagementStrategy::Manual)
+        .build()?;
+    Ok(Box::new(fluvio.consumer_with_config(config).await?))
+}
+
+/// Partitions of `topic` with an offset committed by `group`
+pub async fn committed(fluvio: &Fluvio, group: &str, topic: &str) -> Result<BTreeSet<u32>> {
+    Ok(fluvio
+        .consumer_offsets()
+        .await?
+        .into_iter()
+        .filter(|offset| offset.consumer_id == group && offset.topic == topic)
+        .map(|offset| offset.partition)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::time::Duration;
+
+    #[rstest]
+    #[case("beginning", StartFrom::Beginning)]
+    #[case("end", StartFrom::End)]
+    #[case("1234", StartFrom::Offset(1234))]
+    #[case(
+        "2025-10-01T12:00:00Z",
+        StartFrom::Timestamp(UNIX_EPOCH + Duration::from_secs(1_759_320_000))
+    )]
+    fn parse_start_from(#[case] value: &str, #[case] expected: StartFrom) {
+        let start_from: StartFrom = value.parse().unwrap();
+        assert_eq!(start_from, expected);
+        assert_eq!(
+            start_from.to_string().parse::<StartFrom>().unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    fn invalid_start_from() {
+        assert!("yesterday".parse::<StartFrom>().is_err());
+    }
+
+    #[rstest]
+    #[case(None, Offset::beginning())]
+    #[case(Some(StartFrom::Beginning), Offset::beginning())]
+    #[case(Some(StartFrom::End), Offset::end())]
+    #[case(Some(StartFrom::Offset(42)), Offset::absolute(42).unwrap())]
+    #[case(
+        Some(StartFrom::Timestamp(UNIX_EPOCH + Duration::from_secs(1_759_320_000))),
+        Offset::beginning()
+    )]
+    fn start_offsets(#[case] start_from: Option<StartFrom>, #[case] expected: Offset) {
+        assert_eq!(offset_start(start_from).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(0, 1_759_319_999_999, true)]
+    #[case(0, 1_759_320_000_000, false)]
+    #[case(0, 1_759_320_000_001, false)]
+    #[case(0, -1, false)]
+    #[case(1, 1_759_319_999_999, false)]
+    fn records_before_the_start_are_skipped(
+        #[case] partition: u32,
+        #[case] timestamp: i64,
+        #[case] skipped: bool,
+    ) {
+        let not_before = BTreeMap::from([(0, 1_759_320_000_000)]);
+        assert_eq!(before(&not_before, partition, timestamp), skipped);
+    }
+
+    #[rstest]
+    #[case(0, 1, vec![0, 1, 2, 3, 4, 5])]
+    #[case(0, 2, vec![0, 2, 4])]
+    #[case(1, 2, vec![1, 3, 5])]
+    #[case(3, 4, vec![3])]
+    fn assigned_partitions(
+        #[case] replica: u32,
+        #[case] replicas: u32,
+        #[case] expected: Vec<u32>,
+    ) {
+        let assignment = Assignment::new(replica, replicas).unwrap();
+        assert_eq!(assignment.partitions(6), expected);
+    }
+
+    #[rstest]
+    fn replicas_cover_every_partition_once() {
+        let mut partitions: Vec<u32> = (0..3)
+            .flat_map(|replica| Assignment::new(replica, 3).unwrap().partitions(8))
+            .collect();
+        partitions.sort();
+        assert_eq!(partitions, (0..8).collect::<Vec<_>>());
+    }
+
+    #[rstest]
+    #[case("ott-filter-0", Some(0))]
+    #[case("ott-filter-12", Some(12))]
+    #[case("ott-filter", None)]
+    fn pod_ordinal(#[case] pod: &str, #[case] expected: Option<u32>) {
+        assert_eq!(ordinal(pod).ok(), expected);
+    }
+
+    #[rstest]
+    fn replica_out_of_range() {
+        assert!(Assignment::new(2, 2).is_err());
+    }
+}
@@ -4,6 +4,9 @@ use serde::{Deserialize, Serialize};
 pub struct RawPost {
     pub did: String,
     pub uri: String,
+    /// Jetstream time of the event, unix micros
+    #[serde(default)]
+    pub time_us: u64,
     pub commit: Commit,
 }
 
@@ -18,24 +21,108 @@ pub enum Commit {
 #[derive(Debug, Deserialize, Clone)]
 pub struct Record {
     pub text: String,
+    #[serde(default)]
+    pub langs: Option<Vec<String>>,
+    #[serde(default)]
+    pub reply: Option<ReplyRef>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// Posts replied to, present only on replies
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReplyRef {
+    pub root: StrongRef,
+    pub parent: StrongRef,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StrongRef {
+    pub uri: String,
+    pub cid: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct Post {
     pub did: String,
     pub uri: String,
     pub text: String,
     pub count: u32,
+    /// Like velocity, the likes decayed by their age as of `scored_at_us`
+    #[serde(default)]
+    pub score: f64,
+    /// Time of the last like counted in `score`, unix micros
+    #[serde(default)]
+    pub scored_at_us: u64,
+    #[serde(default)]
+    pub langs: Vec<String>,
+    #[serde(default)]
+    pub reply: bool,
+    /// Failed attempts to embed and store the post, set when a dead letter is replayed
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub attempts: u32,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// Post deleted by its author, published by ott-filter so it is never served
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeletedPost {
+    pub uri: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Like {
     pub did: String,
     pub uri: String,
+    /// Jetstream time of the event, unix micros
+    #[serde(default)]
+    pub time_us: u64,
+}
+
+/// User of a feed, every post they like passes the filter
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VipUser {
+    pub did: String,
+}
+
+/// Record a service could not handle, published to its dead letter topic
+/// to be inspected or replayed later instead of stopping the service
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeadLetter {
+    /// Topic the record was consumed from, a replay publishes it there again
+    pub topic: String,
+    /// Position of the record, none when it failed past the consumer
+    #[serde(default)]
+    pub partition: Option<u32>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+    /// Kind of failure, like `malformed` or `embedding`
+    #[serde(default)]
+    pub reason: String,
+    /// The error of the last attempt
+    pub error: String,
+    /// How often handling the record was tried
+    #[serde(default)]
+    pub attempts: u32,
+    /// The record as consumed, lossy when it is not UTF-8
+    pub value: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct Embedding {
     pub uri: String,
     pub vector: Vec<f32>,
+    /// Like velocity of the post when it was forwarded, 0 when not known
+    pub score: f64,
+}
+
+/// Feed interaction sent by a client, `event` is the lexicon token
+/// (for example `app.bsky.feed.defs#requestLess`) and `item` the post uri
+#[derive(Debug, Clone)]
+pub struct Interaction {
+    pub did: String,
+    pub item: String,
+    pub event: String,
+    pub feed_context: Option<String>,
 }
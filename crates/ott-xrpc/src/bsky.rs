@@ -1,115 +1,273 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use clap::Args;
 use jacquard::api::app_bsky::actor::get_profiles::GetProfiles;
+use jacquard::client::credential_session::CredentialSession;
 use jacquard::client::Agent;
-use jacquard::client::AgentSessionExt;
-use jacquard::client::BasicClient;
-use jacquard::client::MemorySessionStore;
 use jacquard::from_data_owned;
+use jacquard::types::aturi::AtUri;
+use jacquard::types::collection::Collection;
 use jacquard::types::ident::AtIdentifier;
 use jacquard::types::nsid::Nsid;
-use jacquard::xrpc::XrpcExt;
+use jacquard::xrpc::{XrpcClient, XrpcExt};
 use jacquard::CowStr;
+use jacquard::IntoStatic;
+use jacquard_api::app_bsky::feed::get_posts::GetPosts;
+use jacquard_api::app_bsky::feed::like::Like;
 use jacquard_api::app_bsky::feed::post::Post;
-use jacquard_api::com_atproto::repo::list_records::ListRecords;
-use tracing::{info, warn};
+use jacquard_api::app_bsky::feed::PostView;
+use jacquard_api::com_atproto::repo::get_record::GetRecord;
+use jacquard_api::com_atproto::repo::list_records::{ListRecords, Record};
+use jacquard_identity::resolver::{IdentityResolver, ResolverOptions};
+use jacquard_identity::JacquardResolver;
+use moka::sync::Cache;
+use tracing::{debug, info, warn};
 use url::Url;
 
-// Super silly, there must be some good traits to use
-type MyAgent = Agent<
-    jacquard::client::credential_session::CredentialSession<
-        MemorySessionStore<
-            (jacquard::types::did::Did<'static>, CowStr<'static>),
-            jacquard::client::AtpSession,
-        >,
-        Agent<
-            jacquard::client::credential_session::CredentialSession<
-                MemorySessionStore<
-                    (jacquard::types::did::Did<'static>, CowStr<'static>),
-                    jacquard::client::AtpSession,
-                >,
-                jacquard_identity::JacquardResolver,
-            >,
-        >,
-    >,
->;
+use crate::session_store::SessionFileStore;
+
+type MyAgent = Agent<CredentialSession<SessionFileStore, JacquardResolver>>;
+
+/// How BskyClient talks to Bluesky. Public reads need no account, an account
+/// is only used for reads on behalf of the service when configured.
+#[derive(Debug, Clone, Args)]
+pub struct BskyArgs {
+    /// AppView serving the public app.bsky reads
+    #[arg(
+        long,
+        env = "BSKY_APPVIEW",
+        default_value = "https://public.api.bsky.app"
+    )]
+    pub appview: Url,
+
+    /// Handle or DID of the account to log in with, anonymous when not set
+    #[arg(long = "bsky-identifier", env = "APP_DID", requires = "password")]
+    pub identifier: Option<String>,
+
+    /// App password of the account
+    #[arg(long = "bsky-password", env = "APP_KEY", hide_env_values = true)]
+    pub password: Option<String>,
+
+    /// File to keep the session in, so restarts don't log in again
+    #[arg(long = "bsky-session-file", env = "BSKY_SESSION_FILE")]
+    pub session_file: Option<PathBuf>,
+}
+
+/// How long the PDS of a repo is remembered, repos rarely move
+const PDS_TTL: Duration = Duration::from_secs(60 * 60);
+const PDS_CACHE_CAPACITY: u64 = 10_000;
+
+/// Most uris `app.bsky.feed.getPosts` takes at once
+const GET_POSTS_MAX: usize = 25;
+
+/// A like record, `like.subject` is the liked post
+#[derive(Debug, Clone)]
+pub struct LikeRecord {
+    pub uri: AtUri<'static>,
+    pub like: Like<'static>,
+}
+
+/// A page of likes and the cursor to the next one, if any
+#[derive(Debug, Clone)]
+pub struct Likes {
+    pub records: Vec<LikeRecord>,
+    pub cursor: Option<String>,
+}
+
 pub struct BskyClient {
-    pub agent: MyAgent,
+    /// Logged in agent, `None` when anonymous
+    agent: Option<MyAgent>,
+    /// AppView for the app.bsky reads, repo reads go to the PDS of the repo
     pub base_url: Url,
+    http: reqwest::Client,
+    resolver: JacquardResolver,
+    pds: Cache<String, Url>,
 }
 
 impl BskyClient {
-    pub async fn new() -> Result<Self> {
-        let app_did = std::env::var("APP_DID").expect("Need to set APP_DID");
-        let app_key = std::env::var("APP_KEY").expect("Need to set APP_KEY");
-        let base = url::Url::parse("https://public.api.bsky.app")?;
-        let session = jacquard::client::credential_session::CredentialSession::new(
-            Arc::new(MemorySessionStore::default()),
-            Arc::new(BasicClient::default()),
-        );
-        session
+    pub async fn new(args: &BskyArgs) -> Result<Self> {
+        let resolver = JacquardResolver::new(reqwest::Client::new(), ResolverOptions::default());
+        let client = Self::anonymous(args.appview.clone(), resolver);
+        match (&args.identifier, &args.password) {
+            (Some(identifier), Some(password)) => {
+                let store = SessionFileStore::new(args.session_file.clone())?;
+                client.login(identifier, password, store).await
+            }
+            _ => {
+                info!("No Bluesky account configured, using public reads only");
+                Ok(client)
+            }
+        }
+    }
+
+    pub fn anonymous(base_url: Url, resolver: JacquardResolver) -> Self {
+        Self {
+            agent: None,
+            base_url,
+            http: reqwest::Client::new(),
+            resolver,
+            pds: Cache::builder()
+                .max_capacity(PDS_CACHE_CAPACITY)
+                .time_to_live(PDS_TTL)
+                .build(),
+        }
+    }
+
+    /// Resume the session in `store` when it belongs to `identifier` and
+    /// can still be refreshed, log in with the app password otherwise.
+    /// Expired access tokens are refreshed by the agent on use.
+    async fn login(
+        mut self,
+        identifier: &str,
+        password: &str,
+        store: SessionFileStore,
+    ) -> Result<Self> {
+        let stored = store
+            .account()
+            .filter(|((did, _), handle)| did.as_str() == identifier || handle == identifier);
+        let session = CredentialSession::new(Arc::new(store), Arc::new(self.resolver.clone()));
+
+        if let Some(((did, session_id), _)) = stored {
+            let resumed = match session.restore(did.clone(), session_id).await {
+                Ok(()) => session.refresh().await.map(|_| ()),
+                Err(e) => Err(e),
+            };
+            match resumed {
+                Ok(()) => {
+                    info!("Resumed Bluesky session of {}", did);
+                    self.agent = Some(Agent::from(session));
+                    return Ok(self);
+                }
+                Err(e) => warn!("Could not resume Bluesky session, logging in again: {}", e),
+            }
+        }
+
+        let atp = session
             .login(
-                CowStr::from(app_did),
-                CowStr::from(app_key),
+                CowStr::from(identifier.to_string()),
+                CowStr::from(password.to_string()),
                 None,
                 None,
                 None,
             )
             .await?;
-        let token = session.access_token().await.unwrap();
-        warn!("{:#?}", token);
-        let agent = Agent::from(session);
-        Ok(Self {
-            agent,
-            base_url: base,
-        })
+        info!("Logged in to Bluesky as {}", atp.did);
+        self.agent = Some(Agent::from(session));
+        Ok(self)
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.agent.is_some()
     }
 
-    pub async fn get_like(&self, did: &str) -> Result<Post> {
+    /// PDS hosting the repo of `ident`, from its DID document
+    pub async fn pds_for(&self, ident: &AtIdentifier<'_>) -> Result<Url> {
+        let did = match ident {
+            AtIdentifier::Did(did) => did.clone().into_static(),
+            AtIdentifier::Handle(handle) => self.resolver.resolve_handle(handle).await?,
+        };
+        if let Some(pds) = self.pds.get(did.as_str()) {
+            return Ok(pds);
+        }
+        let pds = self.resolver.pds_for_did(&did).await?;
+        debug!("Repo {} is hosted on {}", did, pds);
+        self.pds.insert(did.to_string(), pds.clone());
+        Ok(pds)
+    }
+
+    /// One page of the likes of `did`, newest first, continuing after `cursor`
+    pub async fn get_likes(&self, did: &str, limit: i64, cursor: Option<&str>) -> Result<Likes> {
+        let repo = AtIdentifier::from_str(did)?;
+        let pds = self.pds_for(&repo).await?;
         let request = ListRecords::new()
-            .collection(Nsid::from_str("bsky.feed.like")?)
-            .limit(1)
-            .repo(AtIdentifier::from_str(did).expect("did to be ok"))
+            .collection(Nsid::new_static(Like::NSID)?)
+            .limit(limit)
+            .repo(repo)
+            .maybe_cursor(cursor.map(|c| CowStr::from(c.to_string())))
             .build();
 
-        let response = self
-            .agent
-            .xrpc(self.base_url.clone())
-            .send(&request)
-            .await?;
-        let data = response
-            .into_output()
-            .unwrap()
-            .records
-            .first()
-            .unwrap()
-            .to_owned();
-        let post: Post = from_data_owned(data)?;
+        let response = self.http.xrpc(pds).send(&request).await?;
+        let output = response.into_output()?;
+        let mut records = Vec::with_capacity(output.records.len());
+        for data in output.records {
+            let record: Record = from_data_owned(data)?;
+            match from_data_owned::<Like>(record.value) {
+                Ok(like) => records.push(LikeRecord {
+                    uri: record.uri.into_static(),
+                    like: like.into_static(),
+                }),
+                Err(e) => warn!("Skipping malformed like {}: {}", record.uri, e),
+            }
+        }
 
-        Ok(post)
+        Ok(Likes {
+            records,
+            cursor: output.cursor.map(|c| c.to_string()),
+        })
+    }
+
+    /// Hydrated views of the posts, in batches of what getPosts allows.
+    /// Deleted or hidden posts are missing from the result, as are the
+    /// posts of batches that failed.
+    pub async fn get_posts(&self, uris: &[String]) -> Vec<PostView<'static>> {
+        let mut posts = Vec::with_capacity(uris.len());
+        for batch in uris.chunks(GET_POSTS_MAX) {
+            match self.get_posts_batch(batch).await {
+                Ok(batch) => posts.extend(batch),
+                Err(e) => warn!("Failed to get {} posts: {}", batch.len(), e),
+            }
+        }
+        posts
+    }
+
+    async fn get_posts_batch(&self, uris: &[String]) -> Result<Vec<PostView<'static>>> {
+        let request = GetPosts::new()
+            .uris(
+                uris.iter()
+                    .map(|uri| AtUri::new_owned(uri))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+            .build();
+        let response = self.http.xrpc(self.base_url.clone()).send(&request).await?;
+        Ok(response
+            .into_output()?
+            .posts
+            .into_iter()
+            .map(IntoStatic::into_static)
+            .collect())
     }
 
-    async fn get_profile(&self, did: &str) -> Result<()> {
+    /// Logs the profile of `did`, as seen by the service account when logged in
+    pub async fn get_profile(&self, did: &str) -> Result<()> {
         let request = GetProfiles::new()
             .actors(vec![AtIdentifier::Did(did.parse()?)])
             .build();
-        let response = self
-            .agent
-            .xrpc(self.base_url.clone())
-            .send(&request)
-            .await?;
+        let response = match &self.agent {
+            Some(agent) => agent.send(request).await?,
+            None => self.http.xrpc(self.base_url.clone()).send(&request).await?,
+        };
         info!("{:#?}", response.parse());
         Ok(())
     }
 
-    async fn get_post(&self, uri: &str) -> Result<()> {
-        let response = self.agent.get_record::<Post>(uri.parse()?).await?;
-        let output = response.into_output()?;
-        info!("{:#?}", output);
+    pub async fn get_post(&self, uri: &str) -> Result<Post<'static>> {
+        let uri = AtUri::new(uri)?;
+        let rkey = uri.rkey().ok_or(anyhow!("Missing rkey in {}", uri))?;
+        let pds = self.pds_for(uri.authority()).await?;
+        let request = GetRecord::new()
+            .repo(uri.authority().clone())
+            .collection(Nsid::new_static(Post::NSID)?)
+            .rkey(rkey.clone())
+            .build();
 
-        Ok(())
+        let response = self.http.xrpc(pds).send(&request).await?;
+        let post: Post = from_data_owned(response.into_output()?.value)?;
+
+        Ok(post.into_static())
     }
 }
 
@@ -117,7 +275,12 @@ impl BskyClient {
 mod test {
 
     use super::*;
+    use axum::{extract::Query, routing::get, Json, Router};
+    use jacquard_identity::resolver::PlcSource;
     use rstest::{fixture, rstest};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[fixture]
     fn setup_tracing() {
@@ -128,32 +291,153 @@ mod test {
     }
 
     #[fixture]
-    async fn client() -> BskyClient {
-        BskyClient::new().await.unwrap()
+    fn client() -> BskyClient {
+        BskyClient::anonymous(
+            Url::parse("https://public.api.bsky.app").unwrap(),
+            JacquardResolver::new(reqwest::Client::new(), ResolverOptions::default()),
+        )
+    }
+
+    const MOCK_DID: &str = "did:plc:mockmockmockmockmockmock";
+    const LIKED_POST: &str =
+        "at://did:plc:klugggc44dmpomjkuzyahzjd/app.bsky.feed.post/3m2y6a5h6os27";
+    const CID: &str = "bafyreig6fjkdrxtp5vrnwfacvv3gjxozmtldhgqpprk7wsfosmvkr7qnjq";
+
+    /// Serves the PLC directory, with a document pointing at itself as the
+    /// PDS of [`MOCK_DID`], the listRecords of that PDS, two pages of one
+    /// like, and the getPosts of an AppView
+    async fn mock_pds(resolutions: Arc<AtomicUsize>) -> Url {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+
+        let pds = base.to_string();
+        let app = Router::new()
+            .route(
+                &format!("/{}", MOCK_DID),
+                get(move || async move {
+                    resolutions.fetch_add(1, Ordering::SeqCst);
+                    Json(json!({
+                        "id": MOCK_DID,
+                        "service": [{
+                            "id": "#atproto_pds",
+                            "type": "AtprotoPersonalDataServer",
+                            "serviceEndpoint": pds,
+                        }],
+                    }))
+                }),
+            )
+            .route(
+                "/xrpc/com.atproto.repo.listRecords",
+                get(|Query(query): Query<HashMap<String, String>>| async move {
+                    let (rkey, cursor) = match query.get("cursor").map(String::as_str) {
+                        None => ("3m2y6a5h6os27", Some("3m2y6a5h6os26")),
+                        Some(_) => ("3m2y6a5h6os26", None),
+                    };
+                    Json(json!({
+                        "cursor": cursor,
+                        "records": [{
+                            "uri": format!("at://{}/app.bsky.feed.like/{}", MOCK_DID, rkey),
+                            "cid": CID,
+                            "value": {
+                                "$type": "app.bsky.feed.like",
+                                "subject": { "uri": LIKED_POST, "cid": CID },
+                                "createdAt": "2025-10-01T12:00:00.000Z",
+                            },
+                        }],
+                    }))
+                }),
+            )
+            .route(
+                "/xrpc/app.bsky.feed.getPosts",
+                get(|| async {
+                    Json(json!({
+                        "posts": [{
+                            "uri": LIKED_POST,
+                            "cid": CID,
+                            "author": {
+                                "did": "did:plc:klugggc44dmpomjkuzyahzjd",
+                                "handle": "someone.bsky.social",
+                            },
+                            "record": {
+                                "$type": "app.bsky.feed.post",
+                                "text": "hello world",
+                                "createdAt": "2025-10-01T11:00:00.000Z",
+                            },
+                            "indexedAt": "2025-10-01T11:00:00.000Z",
+                        }],
+                    }))
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        base
+    }
+
+    /// A client resolving DIDs with the PLC directory at `base` and
+    /// reading from the AppView at `appview`
+    fn mock_client(base: &Url, appview: &str) -> BskyClient {
+        let options = ResolverOptions {
+            plc_source: PlcSource::PlcDirectory { base: base.clone() },
+            ..ResolverOptions::default()
+        };
+        BskyClient::anonymous(
+            Url::parse(appview).unwrap(),
+            JacquardResolver::new(reqwest::Client::new(), options),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_get_likes_from_resolved_pds() {
+        let resolutions = Arc::new(AtomicUsize::new(0));
+        let base = mock_pds(resolutions.clone()).await;
+        let client = mock_client(&base, "http://127.0.0.1:9/");
+
+        for _ in 0..2 {
+            let likes = client.get_likes(MOCK_DID, 10, None).await.unwrap();
+            assert_eq!(likes.records.len(), 1);
+            assert_eq!(likes.records[0].like.subject.uri.as_str(), LIKED_POST);
+            assert_eq!(likes.cursor.as_deref(), Some("3m2y6a5h6os26"));
+        }
+        assert_eq!(resolutions.load(Ordering::SeqCst), 1);
     }
 
     #[rstest]
     #[tokio::test]
     async fn test_get_latest_like(
         #[values("did:plc:klugggc44dmpomjkuzyahzjd")] did: &str,
-        #[future] client: BskyClient,
+        client: BskyClient,
         _setup_tracing: (),
     ) {
         info!("Starting");
-        let client = client.await;
-        let post = client.get_like(did).await;
-        assert!(post.is_ok());
+        let likes = client.get_likes(did, 1, None).await.unwrap();
+        assert_eq!(likes.records.len(), 1);
+        assert!(likes.cursor.is_some());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_get_likes_follows_cursor() {
+        let base = mock_pds(Arc::default()).await;
+        let client = mock_client(&base, "http://127.0.0.1:9/");
+        let first = client.get_likes(MOCK_DID, 1, None).await.unwrap();
+        let second = client
+            .get_likes(MOCK_DID, 1, first.cursor.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(second.records.len(), 1);
+        assert_ne!(first.records[0].uri, second.records[0].uri);
+        assert_eq!(second.cursor, None);
     }
 
     #[rstest]
     #[tokio::test]
     async fn test_get_profile(
         #[values("did:plc:klugggc44dmpomjkuzyahzjd", "did:plc:6u4att3krympska2rcfphobc")] did: &str,
-        #[future] client: BskyClient,
+        client: BskyClient,
         _setup_tracing: (),
     ) {
         info!("Starting");
-        let did = client.await.get_profile(did).await;
+        let did = client.get_profile(did).await;
         info!("{:#?}", did);
 
         assert!(did.is_ok());
@@ -161,5 +445,30 @@ mod test {
 
     #[rstest]
     #[tokio::test]
-    async fn test_get_post(#[future] client: BskyClient) {}
+    async fn test_get_post(
+        #[values("at://did:plc:klugggc44dmpomjkuzyahzjd/app.bsky.feed.post/3m2y6a5h6os27")]
+        uri: &str,
+        client: BskyClient,
+    ) {
+        let post = client.get_post(uri).await;
+        assert!(post.is_ok());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_get_posts() {
+        let base = mock_pds(Arc::default()).await;
+        let client = mock_client(&base, base.as_str());
+        let posts = client.get_posts(&[LIKED_POST.to_string()]).await;
+        assert_eq!(posts.len(), 1);
+        assert_eq!(posts[0].uri.as_str(), LIKED_POST);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_failed_get_posts_are_skipped() {
+        let base = mock_pds(Arc::default()).await;
+        let client = mock_client(&base, "http://127.0.0.1:9/");
+        assert!(client.get_posts(&[LIKED_POST.to_string()]).await.is_empty());
+    }
 }
@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+use clap::Args;
+use jacquard::types::did_doc::{DidDocument, Service, VerificationMethod};
+use jacquard_common::types::string::Did;
+use ott_embed::backend::Backend;
+
+use crate::bsky::BskyArgs;
+use crate::key::{KeyArgs, ServiceKey};
+
+const FEED_GENERATOR_NSID: &str = "app.bsky.feed.generator";
+
+/// Configuration of the xrpc service, from flags or the environment
+#[derive(Debug, Clone, Args)]
+pub struct Config {
+    /// Public hostname the service is reached at, part of the service DID
+    #[arg(long, env = "EXTERNAL_BASE", default_value = "ott.aleeve.dev")]
+    pub hostname: String,
+
+    /// DID of the service, defaults to did:web of the hostname
+    #[arg(long, env = "SERVICE_DID")]
+    pub service_did: Option<String>,
+
+    /// Id of the feed generator service in the did document, without `#`
+    #[arg(long, env = "SERVICE_ID", default_value = "bsky_fg")]
+    pub service_id: String,
+
+    /// Address to listen on
+    #[arg(long, env = "BIND_ADDRESS", default_value = "0.0.0.0:8080")]
+    pub bind: SocketAddr,
+
+    /// DID of the account that published the feed records, defaults to the service DID
+    #[arg(long, env = "PUBLISHER_DID")]
+    pub publisher_did: Option<String>,
+
+    /// Feeds served, as `rkey=strategy` or just the strategy when it is the record key
+    #[arg(
+        long = "feed",
+        env = "FEEDS",
+        value_delimiter = ',',
+        default_value = "ott=similar-to-my-likes,similar-to-my-likes,trending-in-my-topics"
+    )]
+    pub feeds: Vec<String>,
+
+    /// Fluvio topic users requesting feeds are announced on, read by ott-filter
+    #[arg(long, env = "VIP_TOPIC", default_value = "vip-users")]
+    pub vip_topic: String,
+
+    /// Secret to sign feed cursors with, the same on every replica so
+    /// cursors outlive restarts and work on any of them
+    #[arg(long, env = "CURSOR_SECRET", hide_env_values = true)]
+    pub cursor_secret: String,
+
+    #[command(flatten)]
+    pub key: KeyArgs,
+
+    #[command(flatten)]
+    pub bsky: BskyArgs,
+
+    /// Embeds the likes not embedded yet, as ott-embed embeds the posts
+    #[command(flatten)]
+    pub embedder: Backend,
+}
+
+impl Config {
+    pub fn service_did(&self) -> String {
+        self.service_did
+            .clone()
+            .unwrap_or_else(|| format!("did:web:{}", self.hostname))
+    }
+
+    pub fn publisher_did(&self) -> String {
+        self.publisher_did
+            .clone()
+            .unwrap_or_else(|| self.service_did())
+    }
+
+    /// at-uri of the feed generator record with the given record key
+    pub fn feed_uri(&self, rkey: &str) -> String {
+        format!(
+            "at://{}/{}/{}",
+            self.publisher_did(),
+            FEED_GENERATOR_NSID,
+            rkey
+        )
+    }
+
+    pub fn did_document(&self, key: &ServiceKey) -> anyhow::Result<DidDocument<'static>> {
+        let did = self.service_did();
+
+        let verification_method = VerificationMethod {
+            id: format!("{}#atproto", did).into(),
+            r#type: "Multikey".into(),
+            controller: Some(did.clone().into()),
+            public_key_multibase: Some(key.public_key_multibase().into()),
+            extra_data: BTreeMap::default(),
+        };
+
+        let service = Service {
+            id: format!("#{}", self.service_id).into(),
+            service_endpoint: Some(format!("https://{}", self.hostname).into()),
+            r#type: "BskyFeedGenerator".into(),
+            extra_data: BTreeMap::default(),
+        };
+
+        Ok(DidDocument {
+            id: Did::new_owned(did)?,
+            also_known_as: Some(vec![format!("at://{}", self.hostname).into()]),
+            verification_method: Some(vec![verification_method]),
+            service: Some(vec![service]),
+            extra_data: BTreeMap::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::KeyType;
+    use clap::Parser;
+    use rstest::rstest;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        config: Config,
+    }
+
+    fn try_parse(args: &[&str]) -> Result<Config, clap::Error> {
+        TestCli::try_parse_from(std::iter::once("ott-xrpc").chain(args.iter().copied()))
+            .map(|cli| cli.config)
+    }
+
+    fn parse(args: &[&str]) -> Config {
+        let with_secret = [&["--cursor-secret", "secret"], args].concat();
+        try_parse(&with_secret).unwrap()
+    }
+
+    #[rstest]
+    fn cursor_secret_is_required() {
+        let error = try_parse(&[]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[rstest]
+    fn did_defaults_to_did_web() {
+        let config = parse(&["--hostname", "feeds.example.com"]);
+        assert_eq!(config.service_did(), "did:web:feeds.example.com");
+        assert_eq!(config.publisher_did(), "did:web:feeds.example.com");
+    }
+
+    #[rstest]
+    fn hostname_defaults_to_the_published_service() {
+        assert_eq!(parse(&[]).service_did(), "did:web:ott.aleeve.dev");
+    }
+
+    #[rstest]
+    fn feed_uri() {
+        let config = parse(&["--publisher-did", "did:plc:abc"]);
+        assert_eq!(
+            config.feed_uri("one"),
+            "at://did:plc:abc/app.bsky.feed.generator/one"
+        );
+    }
+
+    #[rstest]
+    fn did_document() {
+        let config = parse(&[
+            "--hostname",
+            "feeds.example.com",
+            "--service-did",
+            "did:web:example.com",
+            "--service-id",
+            "feeds",
+        ]);
+        let key = ServiceKey::generate(KeyType::Ed25519);
+        let doc = serde_json::to_value(config.did_document(&key).unwrap()).unwrap();
+
+        assert_eq!(doc["id"], "did:web:example.com");
+        assert_eq!(doc["service"][0]["id"], "#feeds");
+        assert_eq!(
+            doc["service"][0]["serviceEndpoint"],
+            "https://feeds.example.com/"
+        );
+        assert_eq!(
+            doc["verificationMethod"][0]["publicKeyMultibase"],
+            key.public_key_multibase()
+        );
+    }
+}
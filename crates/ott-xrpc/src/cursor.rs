@@ -0,0 +1,172 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const VERSION: u8 = 2;
+/// Version, session, `as_of` and score, followed by the uri
+const FIXED_LEN: usize = 1 + 8 + 8 + 8;
+const MAC_LEN: usize = 16;
+
+/// Where a client is in a paginated feed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    /// Session holding the query vector the first page was ranked with
+    pub session: u64,
+    /// Upper bound on `created_at` in unix micros, fixed at the first page
+    /// so posts inserted while scrolling don't shift later pages
+    pub as_of: i64,
+    /// Score of the last post served
+    pub score: f64,
+    /// Uri of the last post served, tie breaker for equal scores
+    pub uri: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CursorError {
+    Encoding,
+    Length(usize),
+    Version(u8),
+    Signature,
+    /// The session of the cursor is gone, paging on would rank differently
+    Expired,
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CursorError::Encoding => write!(f, "cursor is not valid base64"),
+            CursorError::Length(len) => write!(f, "cursor has unexpected length {}", len),
+            CursorError::Version(v) => write!(f, "unsupported cursor version {}", v),
+            CursorError::Signature => write!(f, "cursor signature mismatch"),
+            CursorError::Expired => write!(f, "cursor expired"),
+        }
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+/// Encodes cursors as url safe base64 of the fields followed by a
+/// truncated HMAC-SHA256, so clients can't forge or edit them. The feed is
+/// signed along, a cursor passed to another feed than the one it was
+/// served by fails to verify
+pub struct CursorCodec {
+    key: Vec<u8>,
+}
+
+impl CursorCodec {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    pub fn encode(&self, feed: &str, cursor: &Cursor) -> String {
+        let mut buf = Vec::with_capacity(FIXED_LEN + cursor.uri.len() + MAC_LEN);
+        buf.push(VERSION);
+        buf.extend_from_slice(&cursor.session.to_be_bytes());
+        buf.extend_from_slice(&cursor.as_of.to_be_bytes());
+        buf.extend_from_slice(&cursor.score.to_be_bytes());
+        buf.extend_from_slice(cursor.uri.as_bytes());
+
+        let tag = self.mac(feed, &buf).finalize().into_bytes();
+        buf.extend_from_slice(&tag[..MAC_LEN]);
+        URL_SAFE_NO_PAD.encode(buf)
+    }
+
+    pub fn decode(&self, feed: &str, cursor: &str) -> Result<Cursor, CursorError> {
+        let buf = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .map_err(|_| CursorError::Encoding)?;
+        if buf.len() < FIXED_LEN + MAC_LEN {
+            return Err(CursorError::Length(buf.len()));
+        }
+
+        let (payload, tag) = buf.split_at(buf.len() - MAC_LEN);
+        self.mac(feed, payload)
+            .verify_truncated_left(tag)
+            .map_err(|_| CursorError::Signature)?;
+        if payload[0] != VERSION {
+            return Err(CursorError::Version(payload[0]));
+        }
+
+        let field = |i: usize| -> [u8; 8] {
+            let start = 1 + i * 8;
+            payload[start..start + 8].try_into().expect("fixed length")
+        };
+        Ok(Cursor {
+            session: u64::from_be_bytes(field(0)),
+            as_of: i64::from_be_bytes(field(1)),
+            score: f64::from_be_bytes(field(2)),
+            uri: String::from_utf8(payload[FIXED_LEN..].to_vec())
+                .map_err(|_| CursorError::Encoding)?,
+        })
+    }
+
+    fn mac(&self, feed: &str, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key length");
+        mac.update(&(feed.len() as u64).to_be_bytes());
+        mac.update(feed.as_bytes());
+        mac.update(payload);
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::{fixture, rstest};
+
+    #[fixture]
+    fn codec() -> CursorCodec {
+        CursorCodec::new(b"test secret")
+    }
+
+    const FEED: &str = "at://did:plc:abc/app.bsky.feed.generator/similar-to-my-likes";
+
+    #[fixture]
+    fn cursor() -> Cursor {
+        Cursor {
+            session: 42,
+            as_of: 1_759_348_166_016_963,
+            score: 0.25,
+            uri: "at://did:plc:someone/app.bsky.feed.post/3m2y6a5h6os27".to_string(),
+        }
+    }
+
+    #[rstest]
+    fn roundtrip(codec: CursorCodec, cursor: Cursor) {
+        let encoded = codec.encode(FEED, &cursor);
+        assert_eq!(codec.decode(FEED, &encoded), Ok(cursor));
+    }
+
+    #[rstest]
+    fn tampered_cursor_is_rejected(codec: CursorCodec, cursor: Cursor) {
+        let mut raw = URL_SAFE_NO_PAD.decode(codec.encode(FEED, &cursor)).unwrap();
+        raw[FIXED_LEN - 1] ^= 1;
+        let tampered = URL_SAFE_NO_PAD.encode(raw);
+        assert_eq!(codec.decode(FEED, &tampered), Err(CursorError::Signature));
+    }
+
+    #[rstest]
+    fn other_key_is_rejected(codec: CursorCodec, cursor: Cursor) {
+        let encoded = CursorCodec::new(b"other secret").encode(FEED, &cursor);
+        assert_eq!(codec.decode(FEED, &encoded), Err(CursorError::Signature));
+    }
+
+    #[rstest]
+    fn other_feed_is_rejected(codec: CursorCodec, cursor: Cursor) {
+        let encoded = codec.encode(FEED, &cursor);
+        let trending = "at://did:plc:abc/app.bsky.feed.generator/trending-in-my-topics";
+        assert_eq!(
+            codec.decode(trending, &encoded),
+            Err(CursorError::Signature)
+        );
+    }
+
+    #[rstest]
+    #[case("not base64!", CursorError::Encoding)]
+    #[case("AAAA", CursorError::Length(3))]
+    fn garbage_is_rejected(codec: CursorCodec, #[case] cursor: &str, #[case] err: CursorError) {
+        assert_eq!(codec.decode(FEED, cursor), Err(err));
+    }
+}
@@ -0,0 +1,64 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tracing::error;
+
+/// Error returned from xrpc handlers, rendered as the standard
+/// `{"error": ..., "message": ...}` xrpc error body
+#[derive(Debug)]
+pub struct XrpcError {
+    status: StatusCode,
+    error: &'static str,
+    message: String,
+}
+
+impl XrpcError {
+    pub fn invalid_request(message: impl ToString) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            error: "InvalidRequest",
+            message: message.to_string(),
+        }
+    }
+
+    pub fn unknown_feed(feed: &str) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            error: "UnknownFeed",
+            message: format!("Unknown feed {}", feed),
+        }
+    }
+
+    pub fn internal(message: impl ToString) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            error: "InternalServerError",
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for XrpcError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::internal(err)
+    }
+}
+
+impl IntoResponse for XrpcError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            error!("{}: {}", self.error, self.message);
+        }
+        (
+            self.status,
+            Json(json!({
+                "error": self.error,
+                "message": self.message,
+            })),
+        )
+            .into_response()
+    }
+}
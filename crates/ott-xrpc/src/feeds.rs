@@ -0,0 +1,139 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+
+use crate::config::Config;
+
+/// How the posts of a feed are ranked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Closest to the recent likes of the requester
+    SimilarToMyLikes,
+    /// Close to the recent likes of the requester, favouring the newest posts
+    TrendingInMyTopics,
+}
+
+impl Strategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::SimilarToMyLikes => "similar-to-my-likes",
+            Strategy::TrendingInMyTopics => "trending-in-my-topics",
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "similar-to-my-likes" => Ok(Strategy::SimilarToMyLikes),
+            "trending-in-my-topics" => Ok(Strategy::TrendingInMyTopics),
+            _ => Err(anyhow!("Unknown feed strategy {}", name)),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The feeds this service generates, by at-uri of their feed generator record
+pub struct FeedRegistry {
+    feeds: Vec<(String, Strategy)>,
+}
+
+impl FeedRegistry {
+    /// Feeds are configured as `rkey=strategy`, or just `rkey` when the
+    /// record key is the name of the strategy
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let feeds = config
+            .feeds
+            .iter()
+            .map(|feed| {
+                let (rkey, strategy) = feed.split_once('=').unwrap_or((feed, feed));
+                Ok((config.feed_uri(rkey), strategy.parse()?))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { feeds })
+    }
+
+    pub fn get(&self, uri: &str) -> Option<Strategy> {
+        self.feeds
+            .iter()
+            .find(|(known, _)| known == uri)
+            .map(|(_, strategy)| *strategy)
+    }
+
+    pub fn uris(&self) -> impl Iterator<Item = &str> {
+        self.feeds.iter().map(|(uri, _)| uri.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use rstest::rstest;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        config: Config,
+    }
+
+    fn registry(feeds: &str) -> Result<FeedRegistry> {
+        let cli = TestCli::try_parse_from([
+            "ott-xrpc",
+            "--cursor-secret",
+            "secret",
+            "--publisher-did",
+            "did:plc:abc",
+            "--feed",
+            feeds,
+        ])?;
+        FeedRegistry::from_config(&cli.config)
+    }
+
+    #[rstest]
+    #[case(
+        "at://did:plc:abc/app.bsky.feed.generator/similar-to-my-likes",
+        Some(Strategy::SimilarToMyLikes)
+    )]
+    #[case(
+        "at://did:plc:abc/app.bsky.feed.generator/ott",
+        Some(Strategy::TrendingInMyTopics)
+    )]
+    #[case("at://did:plc:abc/app.bsky.feed.generator/trending-in-my-topics", None)]
+    #[case("at://did:plc:other/app.bsky.feed.generator/ott", None)]
+    fn dispatch_by_uri(#[case] uri: &str, #[case] strategy: Option<Strategy>) {
+        let registry = registry("similar-to-my-likes,ott=trending-in-my-topics").unwrap();
+        assert_eq!(registry.get(uri), strategy);
+    }
+
+    #[rstest]
+    fn published_ott_feed_is_served_by_default() {
+        let cli = TestCli::try_parse_from([
+            "ott-xrpc",
+            "--cursor-secret",
+            "secret",
+            "--publisher-did",
+            "did:plc:abc",
+        ])
+        .unwrap();
+        let registry = FeedRegistry::from_config(&cli.config).unwrap();
+        assert_eq!(
+            registry.get("at://did:plc:abc/app.bsky.feed.generator/ott"),
+            Some(Strategy::SimilarToMyLikes)
+        );
+        assert_eq!(registry.uris().count(), 3);
+    }
+
+    #[rstest]
+    fn unknown_strategy_is_rejected() {
+        assert!(registry("ott=most-liked").is_err());
+    }
+}
@@ -1,16 +1,210 @@
-use ed25519_dalek::SigningKey;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, ValueEnum};
 use elliptic_curve::rand_core::OsRng;
+use k256::pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding};
+
+// Multicodec prefixes (varint encoded) for multikey encoding
+const ED25519_PUB: [u8; 2] = [0xED, 0x01];
+const SECP256K1_PUB: [u8; 2] = [0xE7, 0x01];
+const P256_PUB: [u8; 2] = [0x80, 0x24];
+const ED25519_PRIV: [u8; 2] = [0x80, 0x26];
+const SECP256K1_PRIV: [u8; 2] = [0x81, 0x26];
+const P256_PRIV: [u8; 2] = [0x86, 0x26];
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum KeyType {
+    Ed25519,
+    Secp256k1,
+    P256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum KeyFormat {
+    /// Multibase encoded multikey, like the did document uses
+    Multibase,
+    /// PKCS#8 PEM
+    Pem,
+}
+
+/// Private key of the service, advertised as `#atproto` in the did document
+#[derive(Clone)]
+pub enum ServiceKey {
+    Ed25519(ed25519_dalek::SigningKey),
+    Secp256k1(k256::ecdsa::SigningKey),
+    P256(p256::ecdsa::SigningKey),
+}
+
+/// Where to load the service key from, the key itself or a file holding it
+/// (for example a mounted k8s secret), either as multibase or PEM
+#[derive(Debug, Clone, Args)]
+pub struct KeyArgs {
+    /// Service private key, multibase multikey or PKCS#8 PEM
+    #[arg(long = "service-key", env = "SERVICE_KEY", hide_env_values = true)]
+    pub key: Option<String>,
+
+    /// File holding the service private key
+    #[arg(
+        long = "service-key-file",
+        env = "SERVICE_KEY_FILE",
+        conflicts_with = "key"
+    )]
+    pub key_file: Option<PathBuf>,
+}
+
+impl KeyArgs {
+    pub fn load(&self) -> Result<ServiceKey> {
+        if let Some(key) = &self.key {
+            return ServiceKey::parse(key);
+        }
+        if let Some(path) = &self.key_file {
+            let key = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read key file {}", path.display()))?;
+            return ServiceKey::parse(&key);
+        }
+        bail!("No service key, set SERVICE_KEY or SERVICE_KEY_FILE (create one with `keygen`)")
+    }
+}
 
-fn multikey_ed25519(key: &[u8]) -> String {
-    let mut buf = vec![0xED, 0x01]; // Ed25519-pub multicodec (varint encoded)
+impl ServiceKey {
+    pub fn generate(key_type: KeyType) -> Self {
+        match key_type {
+            KeyType::Ed25519 => Self::Ed25519(ed25519_dalek::SigningKey::generate(&mut OsRng)),
+            KeyType::Secp256k1 => Self::Secp256k1(k256::ecdsa::SigningKey::random(&mut OsRng)),
+            KeyType::P256 => Self::P256(p256::ecdsa::SigningKey::random(&mut OsRng)),
+        }
+    }
+
+    pub fn key_type(&self) -> KeyType {
+        match self {
+            Self::Ed25519(_) => KeyType::Ed25519,
+            Self::Secp256k1(_) => KeyType::Secp256k1,
+            Self::P256(_) => KeyType::P256,
+        }
+    }
+
+    /// Parses PEM if it looks like PEM and a multibase multikey otherwise
+    pub fn parse(key: &str) -> Result<Self> {
+        let key = key.trim();
+        if key.starts_with("-----BEGIN") {
+            Self::from_pem(key)
+        } else {
+            Self::from_multibase(key)
+        }
+    }
+
+    pub fn from_multibase(key: &str) -> Result<Self> {
+        let (_, bytes) = multibase::decode(key)?;
+        if bytes.len() < 2 {
+            bail!("Key too short");
+        }
+        let (codec, key) = bytes.split_at(2);
+        let codec: [u8; 2] = codec.try_into()?;
+        let key = match codec {
+            ED25519_PRIV => Self::Ed25519(ed25519_dalek::SigningKey::from_bytes(
+                key.try_into().context("Invalid ed25519 key length")?,
+            )),
+            SECP256K1_PRIV => Self::Secp256k1(k256::ecdsa::SigningKey::from_slice(key)?),
+            P256_PRIV => Self::P256(p256::ecdsa::SigningKey::from_slice(key)?),
+            ED25519_PUB | SECP256K1_PUB | P256_PUB => {
+                bail!("Got a public key, the service needs the private key")
+            }
+            _ => bail!("Unsupported multicodec {:02x?}", codec),
+        };
+        Ok(key)
+    }
+
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        if let Ok(key) = ed25519_dalek::SigningKey::from_pkcs8_pem(pem) {
+            return Ok(Self::Ed25519(key));
+        }
+        if let Ok(key) = k256::ecdsa::SigningKey::from_pkcs8_pem(pem) {
+            return Ok(Self::Secp256k1(key));
+        }
+        if let Ok(key) = p256::ecdsa::SigningKey::from_pkcs8_pem(pem) {
+            return Ok(Self::P256(key));
+        }
+        Err(anyhow!(
+            "Not a PKCS#8 ed25519, secp256k1 or P-256 private key"
+        ))
+    }
+
+    /// The `publicKeyMultibase` of the `Multikey` verification method
+    pub fn public_key_multibase(&self) -> String {
+        match self {
+            Self::Ed25519(key) => multikey(ED25519_PUB, key.verifying_key().as_bytes()),
+            Self::Secp256k1(key) => multikey(
+                SECP256K1_PUB,
+                key.verifying_key().to_encoded_point(true).as_bytes(),
+            ),
+            Self::P256(key) => multikey(
+                P256_PUB,
+                key.verifying_key().to_encoded_point(true).as_bytes(),
+            ),
+        }
+    }
+
+    pub fn private_key_multibase(&self) -> String {
+        match self {
+            Self::Ed25519(key) => multikey(ED25519_PRIV, key.as_bytes()),
+            Self::Secp256k1(key) => multikey(SECP256K1_PRIV, &key.to_bytes()),
+            Self::P256(key) => multikey(P256_PRIV, &key.to_bytes()),
+        }
+    }
+
+    pub fn private_key_pem(&self) -> Result<String> {
+        let pem = match self {
+            Self::Ed25519(key) => key.to_pkcs8_pem(LineEnding::LF),
+            Self::Secp256k1(key) => key.to_pkcs8_pem(LineEnding::LF),
+            Self::P256(key) => key.to_pkcs8_pem(LineEnding::LF),
+        }
+        .map_err(|e| anyhow!("Failed to encode key: {}", e))?;
+        Ok(pem.to_string())
+    }
+
+    pub fn encode(&self, format: KeyFormat) -> Result<String> {
+        match format {
+            KeyFormat::Multibase => Ok(self.private_key_multibase()),
+            KeyFormat::Pem => self.private_key_pem(),
+        }
+    }
+}
+
+fn multikey(codec: [u8; 2], key: &[u8]) -> String {
+    let mut buf = codec.to_vec();
     buf.extend_from_slice(key);
     multibase::encode(multibase::Base::Base58Btc, buf)
 }
 
-pub fn generate_key() -> String {
-    // Generate keypair
-    let signing_key = SigningKey::generate(&mut OsRng);
-    let verifying_key = signing_key.verifying_key();
-    let public_key_bytes = verifying_key.as_bytes();
-    multikey_ed25519(public_key_bytes)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn roundtrip(
+        #[values(KeyType::Ed25519, KeyType::Secp256k1, KeyType::P256)] key_type: KeyType,
+        #[values(KeyFormat::Multibase, KeyFormat::Pem)] format: KeyFormat,
+    ) {
+        let key = ServiceKey::generate(key_type);
+        let parsed = ServiceKey::parse(&key.encode(format).unwrap()).unwrap();
+        assert_eq!(parsed.key_type(), key_type);
+        assert_eq!(parsed.public_key_multibase(), key.public_key_multibase());
+    }
+
+    #[rstest]
+    #[case(KeyType::Ed25519, "z6Mk")]
+    #[case(KeyType::Secp256k1, "zQ3s")]
+    #[case(KeyType::P256, "zDn")]
+    fn multikey_prefix(#[case] key_type: KeyType, #[case] prefix: &str) {
+        let key = ServiceKey::generate(key_type);
+        assert!(key.public_key_multibase().starts_with(prefix));
+    }
+
+    #[rstest]
+    fn public_key_is_rejected() {
+        let key = ServiceKey::generate(KeyType::Ed25519);
+        assert!(ServiceKey::parse(&key.public_key_multibase()).is_err());
+    }
 }
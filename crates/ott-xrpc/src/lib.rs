@@ -1,2 +1,10 @@
 pub mod bsky;
+pub mod config;
+pub mod cursor;
+pub mod error;
+pub mod feeds;
 pub mod key;
+pub mod recommend;
+pub mod session_store;
+pub mod vip;
+pub mod webcontext;
@@ -1,103 +1,257 @@
 use std::collections::BTreeMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
 
-use axum::{routing::get, Json, Router};
-use jacquard::types::did_doc::{DidDocument, Service, VerificationMethod};
+use axum::{extract::State, routing::get, Json, Router};
+use clap::{Parser, Subcommand};
 use jacquard_api::app_bsky::feed::{
+    describe_feed_generator::{DescribeFeedGeneratorOutput, DescribeFeedGeneratorRequest, Feed},
     get_feed_skeleton::{GetFeedSkeletonOutput, GetFeedSkeletonRequest},
+    send_interactions::{SendInteractionsOutput, SendInteractionsRequest},
     SkeletonFeedPost,
 };
 use jacquard_axum::did_web::did_web_router;
 use jacquard_axum::ExtractXrpc;
-use jacquard_axum::{
-    service_auth::{ExtractServiceAuth, ServiceAuthConfig},
-    IntoRouter,
+use jacquard_axum::{service_auth::ExtractServiceAuth, IntoRouter};
+use jacquard_common::types::value::to_data;
+use ott_types::Interaction;
+use ott_xrpc::{
+    config::Config,
+    cursor::CursorError,
+    error::XrpcError,
+    key::{KeyFormat, KeyType, ServiceKey},
+    webcontext::WebContext,
 };
-use jacquard_common::types::string::Did;
-use jacquard_identity::resolver::ResolverOptions;
-use jacquard_identity::JacquardResolver;
-use ott_xrpc::{bsky::BskyClient, key::generate_key};
 
 use serde_json::Value;
-use tracing::info;
-use tracing_subscriber::EnvFilter;
+use tracing::{info, warn};
 
 use tower_http::normalize_path::NormalizePathLayer;
 
-async fn handle_wellknown_atproto_did() -> Json<serde_json::Value> {
-    Json::from(Value::from("did:web:ott.aleeve.dev"))
+#[derive(Parser)]
+#[command(about = "Feed generator xrpc service")]
+struct Cli {
+    #[command(flatten)]
+    config: Config,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a service key, write it to a file and print its publicKeyMultibase
+    Keygen {
+        #[arg(long, value_enum, default_value = "ed25519")]
+        key_type: KeyType,
+
+        #[arg(long, value_enum, default_value = "multibase")]
+        format: KeyFormat,
+
+        /// File to write the private key to, must not exist
+        #[arg(long, default_value = "service.key")]
+        out: PathBuf,
+    },
+}
+
+async fn handle_wellknown_atproto_did(State(ctx): State<WebContext>) -> Json<serde_json::Value> {
+    Json::from(Value::from(ctx.service_did.as_str()))
 }
 
 async fn handler(
+    State(ctx): State<WebContext>,
     ExtractServiceAuth(auth): ExtractServiceAuth,
     ExtractXrpc(args): ExtractXrpc<GetFeedSkeletonRequest>,
-) -> Result<Json<GetFeedSkeletonOutput<'static>>, String> {
-    let posts: Vec<SkeletonFeedPost<'static>> = vec![SkeletonFeedPost {
-        post: "at://did:plc:klugggc44dmpomjkuzyahzjd/app.bsky.feed.post/3m2y6a5h6os27"
-            .parse()
-            .map_err(|_| "Failed to parse uri".to_string())?,
-        feed_context: None,
-        extra_data: BTreeMap::default(),
-        reason: None,
-    }];
+) -> Result<Json<GetFeedSkeletonOutput<'static>>, XrpcError> {
+    let Some(strategy) = ctx.feeds.get(args.feed.as_str()) else {
+        return Err(XrpcError::unknown_feed(args.feed.as_str()));
+    };
+
+    // Have the posts the requester likes embedded from now on
+    let vip_ctx = ctx.clone();
+    let did = auth.did().to_string();
+    tokio::spawn(async move {
+        if let Err(e) = vip_ctx.vip.announce(&did).await {
+            warn!("Failed to announce {}: {}", did, e);
+        }
+    });
+
+    // Lexicon bounds, defaults to 50
+    let limit = args.limit.unwrap_or(50).clamp(1, 100);
+    let cursor = args
+        .cursor
+        .as_deref()
+        .map(|cursor| ctx.recommender.decode_cursor(args.feed.as_str(), cursor))
+        .transpose()
+        .map_err(|e| XrpcError::invalid_request(format!("Invalid cursor: {}", e)))?;
+    let page = ctx
+        .recommender
+        .feed(
+            args.feed.as_str(),
+            strategy,
+            auth.did().as_str(),
+            limit,
+            cursor,
+        )
+        .await
+        .map_err(|e| match e.downcast::<CursorError>() {
+            Ok(e) => XrpcError::invalid_request(format!("Invalid cursor: {}", e)),
+            Err(e) => e.into(),
+        })?;
+    info!(
+        "Serving {} {} posts to {}",
+        page.uris.len(),
+        strategy,
+        auth.did()
+    );
+
+    let posts = page
+        .uris
+        .into_iter()
+        .map(|uri| {
+            Ok(SkeletonFeedPost {
+                post: uri.parse().map_err(XrpcError::internal)?,
+                feed_context: None,
+                extra_data: BTreeMap::default(),
+                reason: None,
+            })
+        })
+        .collect::<Result<Vec<SkeletonFeedPost<'static>>, XrpcError>>()?;
 
     let output = GetFeedSkeletonOutput::<'static> {
         feed: posts,
-        cursor: None,
+        cursor: page.cursor.map(Into::into),
         req_id: None,
         extra_data: BTreeMap::default(),
     };
     Ok(Json(output.clone()))
 }
 
+async fn describe_handler(
+    State(ctx): State<WebContext>,
+) -> Result<Json<DescribeFeedGeneratorOutput<'static>>, XrpcError> {
+    let feeds = ctx
+        .feeds
+        .uris()
+        .map(|uri| {
+            let feed = Feed {
+                uri: uri.parse().map_err(XrpcError::internal)?,
+                extra_data: BTreeMap::default(),
+            };
+            to_data(&feed).map_err(XrpcError::internal)
+        })
+        .collect::<Result<Vec<_>, XrpcError>>()?;
+
+    Ok(Json(DescribeFeedGeneratorOutput {
+        did: ctx.service_did.clone(),
+        feeds,
+        links: None,
+        extra_data: BTreeMap::default(),
+    }))
+}
+
+/// Most interactions stored per sendInteractions request
+const MAX_INTERACTIONS: usize = 100;
+
+async fn send_interactions_handler(
+    State(ctx): State<WebContext>,
+    ExtractServiceAuth(auth): ExtractServiceAuth,
+    ExtractXrpc(args): ExtractXrpc<SendInteractionsRequest>,
+) -> Result<Json<SendInteractionsOutput<'static>>, XrpcError> {
+    if args.interactions.len() > MAX_INTERACTIONS {
+        return Err(XrpcError::invalid_request(format!(
+            "At most {} interactions per request",
+            MAX_INTERACTIONS
+        )));
+    }
+    // Interactions without an item or event would be dropped unnoticed
+    let interactions: Vec<Interaction> = args
+        .interactions
+        .iter()
+        .enumerate()
+        .map(|(i, interaction)| {
+            let (Some(item), Some(event)) = (&interaction.item, &interaction.event) else {
+                return Err(XrpcError::invalid_request(format!(
+                    "Interaction {} needs an item and an event",
+                    i
+                )));
+            };
+            Ok(Interaction {
+                did: auth.did().to_string(),
+                item: item.to_string(),
+                event: event.to_string(),
+                feed_context: interaction.feed_context.as_ref().map(|c| c.to_string()),
+            })
+        })
+        .collect::<Result<_, XrpcError>>()?;
+    info!(
+        "Storing {} interactions from {}",
+        interactions.len(),
+        auth.did()
+    );
+    if !interactions.is_empty() {
+        ctx.pg
+            .insert_interactions(&interactions)
+            .await
+            .map_err(XrpcError::internal)?;
+    }
+    Ok(Json(SendInteractionsOutput::default()))
+}
+
+fn keygen(key_type: KeyType, format: KeyFormat, out: PathBuf) -> anyhow::Result<()> {
+    let key = ServiceKey::generate(key_type);
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&out)?;
+    writeln!(file, "{}", key.encode(format)?.trim_end())?;
+    println!("{}", key.public_key_multibase());
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    if let Some(Command::Keygen {
+        key_type,
+        format,
+        out,
+    }) = cli.command
+    {
+        return keygen(key_type, format, out);
+    }
+
     tracing_subscriber::fmt()
         .with_ansi(true) // Colors enabled (default)
         .with_max_level(tracing::Level::INFO)
         .init();
 
     info!("Setup");
-    let did_str = "did:web:ott.aleeve.dev";
-    let did = Did::new_static(did_str);
-
-    let verification_method = VerificationMethod {
-        id: format!("{}#atproto", did_str).into(),
-        r#type: "Multikey".into(),
-        controller: Some("did:web:ott.aleeve.dev".into()),
-        public_key_multibase: Some(generate_key().into()),
-        extra_data: BTreeMap::default(),
-    };
-
-    let service = Service {
-        id: "#bsky_fg".into(),
-        service_endpoint: Some("https://ott.aleeve.dev".into()),
-        r#type: "BskyFeedGenerator".into(),
-        extra_data: BTreeMap::default(),
-    };
-
-    let did_doc: DidDocument = DidDocument {
-        id: did.clone().unwrap(),
-        also_known_as: Some(vec!["at://ott.aleeve.dev".into()]),
-        verification_method: Some(vec![verification_method]),
-        service: Some(vec![service]),
-        extra_data: BTreeMap::default(),
-    };
-
-    let resolver = JacquardResolver::new(reqwest::Client::new(), ResolverOptions::default());
-    let config: ServiceAuthConfig<JacquardResolver> =
-        ServiceAuthConfig::new(did.clone().unwrap(), resolver);
+    let config = cli.config;
+    let ctx = WebContext::new(config.clone()).await?;
 
     let app = Router::new()
         .merge(GetFeedSkeletonRequest::into_router(handler))
-        .with_state(config)
-        .merge(did_web_router(did_doc))
+        .merge(DescribeFeedGeneratorRequest::into_router(describe_handler))
+        .merge(SendInteractionsRequest::into_router(
+            send_interactions_handler,
+        ))
         .route(
             "/.well-known/atproto-did",
             get(handle_wellknown_atproto_did),
         )
+        .with_state(ctx.clone())
+        .merge(did_web_router(ctx.service_document.clone()))
         .layer(NormalizePathLayer::trim_trailing_slash());
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    info!("Starting service");
-    axum::serve(listener, app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(config.bind).await?;
+    info!(
+        "Starting service on {} for {}",
+        config.bind,
+        config.service_did()
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
 }
@@ -0,0 +1,362 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use jacquard::from_data_owned;
+use jacquard_api::app_bsky::feed::post::Post;
+use moka::sync::Cache;
+use ott_embed::{
+    embedder::Embedder,
+    pg_client::{FeedSession, PgClient, Ranking},
+};
+use tracing::{debug, warn};
+
+use crate::bsky::BskyClient;
+use crate::cursor::{Cursor, CursorCodec, CursorError};
+use crate::feeds::Strategy;
+
+/// Number of recent likes used to build the profile of a user
+const PROFILE_LIKES: usize = 25;
+
+/// Number of recent requestMore / requestLess interactions used to adjust the profile
+const PROFILE_FEEDBACK: i64 = 20;
+
+/// Weights of the posts a user asked to see more / less of, relative to
+/// their likes, when moving the profile towards or away from them
+const REQUEST_MORE_WEIGHT: f32 = 0.75;
+const REQUEST_LESS_WEIGHT: f32 = 0.5;
+
+pub const REQUEST_MORE: &str = "app.bsky.feed.defs#requestMore";
+pub const REQUEST_LESS: &str = "app.bsky.feed.defs#requestLess";
+
+/// Score penalty per hour of age in the trending feed, cosine distances
+/// range from 0 to 2 and posts are kept for about two hours
+const TRENDING_RECENCY_WEIGHT: f64 = 0.25;
+
+/// Score bonus per `ln(1 + like velocity)` in the trending feed, a post
+/// forwarded at a velocity of 20 moves up by about 0.15
+const TRENDING_VELOCITY_WEIGHT: f64 = 0.05;
+
+/// How long a feed session can be paged through, the posts are only kept
+/// for about two hours
+const SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+pub struct Page {
+    pub uris: Vec<String>,
+    pub cursor: Option<String>,
+}
+
+pub struct Recommender {
+    bsky: BskyClient,
+    pg: Arc<PgClient>,
+    embedder: Arc<dyn Embedder>,
+    cursors: CursorCodec,
+    /// Sessions stored in Postgres, recently used ones
+    sessions: Cache<u64, Arc<FeedSession>>,
+}
+
+impl Recommender {
+    pub fn new(
+        bsky: BskyClient,
+        pg: Arc<PgClient>,
+        embedder: Arc<dyn Embedder>,
+        cursors: CursorCodec,
+    ) -> Self {
+        Self {
+            bsky,
+            pg,
+            embedder,
+            cursors,
+            sessions: Cache::builder().time_to_live(SESSION_TTL).build(),
+        }
+    }
+
+    /// The cursor of a page served by `feed`
+    pub fn decode_cursor(&self, feed: &str, cursor: &str) -> Result<Cursor, CursorError> {
+        self.cursors.decode(feed, cursor)
+    }
+
+    /// A page of the feed ranked by `strategy` for `did`.
+    /// Continues after `cursor` when given.
+    pub async fn feed(
+        &self,
+        feed: &str,
+        strategy: Strategy,
+        did: &str,
+        limit: i64,
+        cursor: Option<Cursor>,
+    ) -> Result<Page> {
+        let ranking = match strategy {
+            Strategy::SimilarToMyLikes => Ranking::default(),
+            Strategy::TrendingInMyTopics => Ranking {
+                recency_weight: TRENDING_RECENCY_WEIGHT,
+                velocity_weight: TRENDING_VELOCITY_WEIGHT,
+            },
+        };
+        self.ranked_page(feed, did, ranking, limit, cursor).await
+    }
+
+    /// Posts similar to what `did` liked recently, most similar first,
+    /// adjusted by `ranking`
+    async fn ranked_page(
+        &self,
+        feed: &str,
+        did: &str,
+        ranking: Ranking,
+        limit: i64,
+        cursor: Option<Cursor>,
+    ) -> Result<Page> {
+        let (session_id, session, as_of, after) = match cursor {
+            Some(c) => {
+                let session = self.stored_session(c.session, did).await?;
+                (c.session, session, c.as_of, Some((c.score, c.uri)))
+            }
+            None => {
+                let Some(session) = self.new_session(did).await? else {
+                    debug!("No embeddable likes for {}", did);
+                    return Ok(Page {
+                        uris: vec![],
+                        cursor: None,
+                    });
+                };
+                let id = rand::random();
+                self.pg.insert_session(id as i64, &session).await?;
+                let session = Arc::new(session);
+                self.sessions.insert(id, session.clone());
+                (id, session, now_micros(), None)
+            }
+        };
+
+        let rows = self
+            .pg
+            .nearest_page(
+                &session.profile,
+                &session.exclude,
+                as_of,
+                ranking,
+                after.as_ref().map(|(score, uri)| (*score, uri.as_str())),
+                limit,
+            )
+            .await?;
+        let cursor = match rows.last() {
+            Some(last) if rows.len() as i64 == limit => Some(self.cursors.encode(
+                feed,
+                &Cursor {
+                    session: session_id,
+                    as_of,
+                    score: last.score,
+                    uri: last.uri.clone(),
+                },
+            )),
+            _ => None,
+        };
+
+        Ok(Page {
+            uris: rows.into_iter().map(|row| row.uri).collect(),
+            cursor,
+        })
+    }
+
+    /// The session a cursor of `did` continues. A session rebuilt from newer
+    /// likes would rank differently, so one that is gone expires the cursor
+    async fn stored_session(&self, id: u64, did: &str) -> Result<Arc<FeedSession>> {
+        let session = match self.sessions.get(&id) {
+            Some(session) => Some(session),
+            None => self
+                .pg
+                .get_session(id as i64, SESSION_TTL)
+                .await?
+                .map(Arc::new),
+        };
+        match session {
+            Some(session) if session.did == did => {
+                self.sessions.insert(id, session.clone());
+                Ok(session)
+            }
+            _ => Err(CursorError::Expired.into()),
+        }
+    }
+
+    /// Session ranking against the likes and interactions of `did`, none
+    /// when there is nothing to build a profile from
+    async fn new_session(&self, did: &str) -> Result<Option<FeedSession>> {
+        let mut exclude = self.liked_uris(did).await?;
+        let liked = self.liked_vectors(&exclude).await?;
+        let more = self
+            .pg
+            .get_interaction_embeddings(did, REQUEST_MORE, PROFILE_FEEDBACK)
+            .await?;
+        let less = self
+            .pg
+            .get_interaction_embeddings(did, REQUEST_LESS, PROFILE_FEEDBACK)
+            .await?;
+        debug!(
+            "Profile of {} from {} likes, {} more, {} less",
+            did,
+            liked.len(),
+            more.len(),
+            less.len()
+        );
+
+        let more: Vec<Vec<f32>> = more.into_iter().map(|e| e.vector).collect();
+        let mut less_vectors = Vec::with_capacity(less.len());
+        for embedding in less {
+            exclude.push(embedding.uri);
+            less_vectors.push(embedding.vector);
+        }
+        Ok(
+            profile_vector(&liked, &more, &less_vectors).map(|profile| FeedSession {
+                did: did.to_string(),
+                profile,
+                exclude,
+            }),
+        )
+    }
+
+    /// Uris of the posts `did` liked most recently, following the
+    /// listRecords cursor until there are enough
+    async fn liked_uris(&self, did: &str) -> Result<Vec<String>> {
+        let mut uris = Vec::with_capacity(PROFILE_LIKES);
+        let mut cursor = None;
+        while uris.len() < PROFILE_LIKES {
+            let limit = (PROFILE_LIKES - uris.len()) as i64;
+            let likes = self.bsky.get_likes(did, limit, cursor.as_deref()).await?;
+            if likes.records.is_empty() {
+                break;
+            }
+            uris.extend(
+                likes
+                    .records
+                    .into_iter()
+                    .map(|record| record.like.subject.uri.to_string()),
+            );
+            match likes.cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        Ok(uris)
+    }
+
+    /// Embeddings of the liked posts, taken from the vectors table when
+    /// available and otherwise computed from the post text
+    async fn liked_vectors(&self, uris: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(uris.len());
+        let mut missing: HashSet<&str> = uris.iter().map(String::as_str).collect();
+        for embedding in self.pg.get_embeddings(uris).await? {
+            missing.remove(embedding.uri.as_str());
+            vectors.push(embedding.vector);
+        }
+        if missing.is_empty() {
+            return Ok(vectors);
+        }
+
+        let missing: Vec<String> = missing.into_iter().map(str::to_string).collect();
+        let mut texts = Vec::new();
+        for view in self.bsky.get_posts(&missing).await {
+            match from_data_owned::<Post>(view.record) {
+                Ok(post) => texts.push(post.text.to_string()),
+                Err(e) => warn!("Failed to decode liked post {}: {}", view.uri, e),
+            }
+        }
+        if texts.is_empty() {
+            return Ok(vectors);
+        }
+        let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+        match self.embedder.embed_batch(&texts).await {
+            Ok(embedded) => vectors.extend(embedded),
+            Err(e) => warn!("Failed to embed {} liked posts: {}", texts.len(), e),
+        }
+        Ok(vectors)
+    }
+}
+
+fn now_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or_default()
+}
+
+/// Mean of the liked posts, moved towards the posts asked to see more of and
+/// away from the ones asked to see less of (Rocchio style relevance feedback).
+/// Cosine distance ignores the length, so the result is not normalized.
+pub fn profile_vector(
+    liked: &[Vec<f32>],
+    more: &[Vec<f32>],
+    less: &[Vec<f32>],
+) -> Option<Vec<f32>> {
+    let mut profile = match (mean_vector(liked), mean_vector(more)) {
+        (Some(mut profile), Some(more)) if more.len() == profile.len() => {
+            for (p, m) in profile.iter_mut().zip(more) {
+                *p += REQUEST_MORE_WEIGHT * m;
+            }
+            profile
+        }
+        (Some(profile), _) => profile,
+        (None, Some(more)) => more,
+        (None, None) => return None,
+    };
+    if let Some(less) = mean_vector(less)
+        && less.len() == profile.len()
+    {
+        for (p, l) in profile.iter_mut().zip(less) {
+            *p -= REQUEST_LESS_WEIGHT * l;
+        }
+    }
+    Some(profile)
+}
+
+/// Element wise mean, `None` if there is nothing to average
+pub fn mean_vector(vectors: &[Vec<f32>]) -> Option<Vec<f32>> {
+    let dim = vectors.first()?.len();
+    let mut mean = vec![0.0; dim];
+    let mut count = 0;
+    for vector in vectors.iter().filter(|v| v.len() == dim) {
+        for (m, v) in mean.iter_mut().zip(vector) {
+            *m += v;
+        }
+        count += 1;
+    }
+    mean.iter_mut().for_each(|m| *m /= count as f32);
+    Some(mean)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn mean_of_vectors() {
+        let mean = mean_vector(&[vec![1.0, 2.0], vec![3.0, 4.0]]);
+        assert_eq!(mean, Some(vec![2.0, 3.0]));
+    }
+
+    #[rstest]
+    fn mean_skips_mismatched_dimensions() {
+        let mean = mean_vector(&[vec![1.0, 2.0], vec![3.0]]);
+        assert_eq!(mean, Some(vec![1.0, 2.0]));
+    }
+
+    #[rstest]
+    fn mean_of_nothing() {
+        assert_eq!(mean_vector(&[]), None);
+    }
+
+    #[rstest]
+    #[case(vec![vec![1.0, 0.0]], vec![], vec![], Some(vec![1.0, 0.0]))]
+    #[case(vec![vec![1.0, 0.0]], vec![vec![0.0, 1.0]], vec![], Some(vec![1.0, 0.75]))]
+    #[case(vec![vec![1.0, 0.0]], vec![], vec![vec![0.0, 1.0]], Some(vec![1.0, -0.5]))]
+    #[case(vec![], vec![vec![0.0, 1.0]], vec![vec![1.0, 0.0]], Some(vec![-0.5, 1.0]))]
+    #[case(vec![], vec![], vec![vec![1.0, 0.0]], None)]
+    fn profile_from_feedback(
+        #[case] liked: Vec<Vec<f32>>,
+        #[case] more: Vec<Vec<f32>>,
+        #[case] less: Vec<Vec<f32>>,
+        #[case] expected: Option<Vec<f32>>,
+    ) {
+        assert_eq!(profile_vector(&liked, &more, &less), expected);
+    }
+}
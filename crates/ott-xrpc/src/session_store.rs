@@ -0,0 +1,167 @@
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use jacquard::client::credential_session::SessionKey;
+use jacquard::client::{AtpSession, SessionStore, SessionStoreError};
+use jacquard::types::string::{Did, Handle};
+use jacquard::CowStr;
+use serde::{Deserialize, Serialize};
+
+/// App password session as written to the session file
+#[derive(Serialize, Deserialize)]
+struct StoredSession {
+    session_id: String,
+    did: String,
+    handle: String,
+    access_jwt: String,
+    refresh_jwt: String,
+}
+
+/// Store for the one app password session of the service. Keeps it in
+/// memory and, when given a path, in a file only the owner can read, so a
+/// restart resumes the session instead of logging in again.
+pub struct SessionFileStore {
+    path: Option<PathBuf>,
+    session: Mutex<Option<(SessionKey, AtpSession)>>,
+}
+
+impl SessionFileStore {
+    pub fn new(path: Option<PathBuf>) -> Result<Self> {
+        let session = match &path {
+            Some(path) if path.exists() => {
+                let stored: StoredSession = serde_json::from_slice(&std::fs::read(path)?)?;
+                Some(from_stored(stored)?)
+            }
+            _ => None,
+        };
+        Ok(Self {
+            path,
+            session: Mutex::new(session),
+        })
+    }
+
+    /// Key and handle of the stored session, to restore it with
+    pub fn account(&self) -> Option<(SessionKey, String)> {
+        let session = self.session.lock().expect("session lock poisoned");
+        session
+            .as_ref()
+            .map(|(key, s)| (key.clone(), s.handle.to_string()))
+    }
+
+    fn write(&self, key: &SessionKey, session: &AtpSession) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let stored = StoredSession {
+            session_id: key.1.to_string(),
+            did: session.did.to_string(),
+            handle: session.handle.to_string(),
+            access_jwt: session.access_jwt.to_string(),
+            refresh_jwt: session.refresh_jwt.to_string(),
+        };
+        let tmp = path.with_extension("tmp");
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.write_all(&serde_json::to_vec(&stored)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+fn from_stored(stored: StoredSession) -> Result<(SessionKey, AtpSession)> {
+    let did = Did::new_owned(stored.did)?;
+    let key = (did.clone(), CowStr::from(stored.session_id));
+    let session = AtpSession {
+        access_jwt: stored.access_jwt.into(),
+        refresh_jwt: stored.refresh_jwt.into(),
+        did,
+        handle: Handle::new_owned(stored.handle)?,
+    };
+    Ok((key, session))
+}
+
+impl SessionStore<SessionKey, AtpSession> for SessionFileStore {
+    async fn get(&self, key: &SessionKey) -> Option<AtpSession> {
+        let session = self.session.lock().expect("session lock poisoned");
+        match session.as_ref() {
+            Some((stored, session)) if stored == key => Some(session.clone()),
+            _ => None,
+        }
+    }
+
+    async fn set(&self, key: SessionKey, session: AtpSession) -> Result<(), SessionStoreError> {
+        self.write(&key, &session)?;
+        *self.session.lock().expect("session lock poisoned") = Some((key, session));
+        Ok(())
+    }
+
+    async fn del(&self, key: &SessionKey) -> Result<(), SessionStoreError> {
+        let mut session = self.session.lock().expect("session lock poisoned");
+        if session.as_ref().is_some_and(|(stored, _)| stored == key) {
+            *session = None;
+            if let Some(path) = &self.path
+                && path.exists()
+            {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn session() -> (SessionKey, AtpSession) {
+        let did = Did::new_static("did:plc:klugggc44dmpomjkuzyahzjd").unwrap();
+        let session = AtpSession {
+            access_jwt: "access".into(),
+            refresh_jwt: "refresh".into(),
+            did: did.clone(),
+            handle: Handle::new_static("example.bsky.social").unwrap(),
+        };
+        ((did, CowStr::from("session")), session)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn session_survives_restart() {
+        let path = std::env::temp_dir().join(format!("ott-session-{}.json", rand::random::<u64>()));
+        let (key, atp) = session();
+
+        let store = SessionFileStore::new(Some(path.clone())).unwrap();
+        assert!(store.account().is_none());
+        store.set(key.clone(), atp).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let restarted = SessionFileStore::new(Some(path.clone())).unwrap();
+        assert_eq!(
+            restarted.account(),
+            Some((key.clone(), "example.bsky.social".to_string()))
+        );
+        let restored = restarted.get(&key).await.unwrap();
+        assert_eq!(restored.refresh_jwt.as_ref(), "refresh");
+
+        restarted.del(&key).await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn memory_only_without_path() {
+        let (key, atp) = session();
+        let store = SessionFileStore::new(None).unwrap();
+        store.set(key.clone(), atp).await.unwrap();
+        assert!(store.get(&key).await.is_some());
+    }
+}
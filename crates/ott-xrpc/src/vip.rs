@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use fluvio::{metadata::topic::TopicSpec, Fluvio, RecordKey, TopicProducerPool};
+use moka::sync::Cache;
+use ott_types::VipUser;
+use tracing::{info, warn};
+
+/// How long a user is not announced again after requesting a feed
+const ANNOUNCED_TTL: Duration = Duration::from_secs(60 * 60);
+const ANNOUNCED_CAPACITY: u64 = 100_000;
+
+/// Publishes the users requesting feeds on the VIP topic, ott-filter then
+/// forwards every post they like so their profiles can be embedded
+pub struct VipAnnouncer {
+    producer: Option<TopicProducerPool>,
+    announced: Cache<String, ()>,
+}
+
+impl VipAnnouncer {
+    /// Announces on `topic`, creating it when missing. Without a reachable
+    /// Fluvio cluster feeds are still served, users are just not announced
+    pub async fn connect(topic: &str) -> Self {
+        match producer(topic).await {
+            Ok(producer) => Self::new(Some(producer)),
+            Err(e) => {
+                warn!("VIP users will not be announced, no Fluvio: {}", e);
+                Self::new(None)
+            }
+        }
+    }
+
+    fn new(producer: Option<TopicProducerPool>) -> Self {
+        Self {
+            producer,
+            announced: Cache::builder()
+                .max_capacity(ANNOUNCED_CAPACITY)
+                .time_to_live(ANNOUNCED_TTL)
+                .build(),
+        }
+    }
+
+    /// Announce `did` unless it was announced recently
+    pub async fn announce(&self, did: &str) -> Result<()> {
+        let Some(producer) = &self.producer else {
+            return Ok(());
+        };
+        if !self.is_new(did) {
+            return Ok(());
+        }
+        let user = VipUser {
+            did: did.to_string(),
+        };
+        let sent = producer
+            .send(RecordKey::from(did), serde_json::to_string(&user)?)
+            .await;
+        if sent.is_err() {
+            // Try again on the next request
+            self.announced.invalidate(did);
+        }
+        sent?;
+        Ok(())
+    }
+
+    fn is_new(&self, did: &str) -> bool {
+        self.announced
+            .entry(did.to_string())
+            .or_insert(())
+            .is_fresh()
+    }
+}
+
+async fn producer(topic: &str) -> Result<TopicProducerPool> {
+    let fluvio = Fluvio::connect().await?;
+    let admin = fluvio.admin().await;
+    let exists = admin
+        .all::<TopicSpec>()
+        .await?
+        .iter()
+        .any(|existing| existing.name == topic);
+    if !exists {
+        info!("Creating VIP topic {}", topic);
+        admin
+            .create(
+                topic.to_string(),
+                false,
+                TopicSpec::new_computed(1, 1, None),
+            )
+            .await?;
+    }
+    fluvio.topic_producer(topic).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    fn announces_each_user_once() {
+        let announcer = VipAnnouncer::new(None);
+        assert!(announcer.is_new("did:plc:one"));
+        assert!(!announcer.is_new("did:plc:one"));
+        assert!(announcer.is_new("did:plc:two"));
+    }
+}
@@ -1,135 +1,124 @@
-use anyhow::Result;
-use async_trait::async_trait;
-use atproto_identity::config::{optional_env, CertificateBundles, DnsNameservers};
-use atproto_identity::resolve::{HickoryDnsResolver, SharedIdentityResolver};
-use atproto_identity::{
-    key::{KeyData, KeyProvider},
-    resolve::InnerIdentityResolver,
-    storage_lru::LruDidDocumentStorage,
-};
-use serde_json::json;
 use std::ops::Deref;
-use std::{collections::HashMap, num::NonZeroUsize, sync::Arc};
+use std::sync::Arc;
+use std::time::Duration;
 
-use jacquard_axum::{ExtractXrpc, IntoRouter};
-
-use atproto_identity::{resolve::IdentityResolver, storage::DidDocumentStorage};
+use anyhow::Result;
 use axum::extract::FromRef;
-
-#[derive(Clone)]
-pub struct SimpleKeyProvider {
-    keys: HashMap<String, KeyData>,
-}
-
-impl Default for SimpleKeyProvider {
-    fn default() -> Self {
-        Self::new()
-    }
+use jacquard::types::did_doc::DidDocument;
+use jacquard_axum::service_auth::ServiceAuth;
+use jacquard_common::types::string::{Did, Handle};
+use jacquard_identity::resolver::{
+    DidDocResponse, IdentityError, IdentityResolver, ResolverOptions,
+};
+use jacquard_identity::JacquardResolver;
+use moka::sync::Cache;
+use ott_embed::pg_client::PgClient;
+
+use crate::bsky::BskyClient;
+use crate::config::Config;
+use crate::cursor::CursorCodec;
+use crate::feeds::FeedRegistry;
+use crate::key::ServiceKey;
+use crate::recommend::Recommender;
+use crate::vip::VipAnnouncer;
+
+/// How long resolved DID documents are trusted before resolving them again
+const DID_DOC_TTL: Duration = Duration::from_secs(10 * 60);
+const DID_DOC_CAPACITY: u64 = 10_000;
+
+/// Identity resolver that keeps successfully resolved DID documents around,
+/// every feed request is service auth'd against the requester's DID document
+pub struct CachingResolver<R> {
+    inner: R,
+    documents: Cache<String, DidDocResponse>,
 }
 
-impl SimpleKeyProvider {
-    pub fn new() -> Self {
+impl<R> CachingResolver<R> {
+    pub fn new(inner: R) -> Self {
         Self {
-            keys: HashMap::new(),
+            inner,
+            documents: Cache::builder()
+                .max_capacity(DID_DOC_CAPACITY)
+                .time_to_live(DID_DOC_TTL)
+                .build(),
         }
     }
 }
 
-#[async_trait]
-impl KeyProvider for SimpleKeyProvider {
-    async fn get_private_key_by_id(&self, key_id: &str) -> anyhow::Result<Option<KeyData>> {
-        Ok(self.keys.get(key_id).cloned())
+impl<R: IdentityResolver + Sync> IdentityResolver for CachingResolver<R> {
+    fn options(&self) -> &ResolverOptions {
+        self.inner.options()
     }
-}
 
-#[derive(Clone)]
-pub struct ServiceDocument(pub serde_json::Value);
+    async fn resolve_handle(&self, handle: &Handle<'_>) -> Result<Did<'static>, IdentityError> {
+        self.inner.resolve_handle(handle).await
+    }
 
-#[derive(Clone)]
-pub struct ServiceDID(pub String);
+    async fn resolve_did_doc(&self, did: &Did<'_>) -> Result<DidDocResponse, IdentityError> {
+        if let Some(doc) = self.documents.get(did.as_str()) {
+            return Ok(doc);
+        }
+        let doc = self.inner.resolve_did_doc(did).await?;
+        if doc.status.is_success() {
+            self.documents.insert(did.as_str().to_string(), doc.clone());
+        }
+        Ok(doc)
+    }
+}
 
 pub struct InnerWebContext {
+    pub config: Config,
     pub http_client: reqwest::Client,
-    pub document_storage: Arc<dyn DidDocumentStorage>,
-    pub key_provider: Arc<dyn KeyProvider>,
-    pub service_document: ServiceDocument,
-    pub service_did: ServiceDID,
-    pub identity_resolver: Arc<dyn IdentityResolver>,
+    pub service_did: Did<'static>,
+    pub service_document: DidDocument<'static>,
+    pub service_key: ServiceKey,
+    pub identity_resolver: CachingResolver<JacquardResolver>,
+    pub pg: Arc<PgClient>,
+    pub feeds: FeedRegistry,
+    pub recommender: Recommender,
+    pub vip: VipAnnouncer,
 }
 
-#[derive(Clone, FromRef)]
+/// Application state shared by all handlers
+#[derive(Clone)]
 pub struct WebContext(pub Arc<InnerWebContext>);
 
-pub struct ContextConfig {
-    pub public_service_key: String,
-    pub private_service_key_data: KeyData,
-    pub service_did: String,
-    pub plc_hostname: String,
-    pub external_base: String,
-    pub dns_nameservers: DnsNameservers,
-    pub user_agent: String,
-}
-
 impl WebContext {
-    pub fn new(config: ContextConfig) -> Result<Self> {
-        let signing_key_storage = HashMap::from_iter(vec![(
-            config.public_service_key.clone(),
-            config.private_service_key_data.clone(),
-        )]);
-
-        let certificate_bundles: CertificateBundles =
-            optional_env("CERTIFICATE_BUNDLES").try_into()?;
-
-        let mut client_builder = reqwest::Client::builder();
-        for ca_certificate in certificate_bundles.as_ref() {
-            let cert = std::fs::read(ca_certificate)?;
-            let cert = reqwest::Certificate::from_pem(&cert)?;
-            client_builder = client_builder.add_root_certificate(cert);
-        }
-
-        client_builder = client_builder.user_agent(config.user_agent);
-        let http_client = client_builder.build()?;
-
-        let dns_resolver = HickoryDnsResolver::create_resolver(config.dns_nameservers.as_ref());
-
-        let service_did = config.service_did.clone();
-        let external_base = config.external_base;
-        let service_document = ServiceDocument(json!({
-                "@context": vec!["https://www.w3.org/ns/did/v1","https://w3id.org/security/multikey/v1"],
-                "id": service_did,
-                "verificationMethod":[{
-                    "id": format!("{service_did}#atproto"),
-                    "type":"Multikey",
-                    "controller": service_did,
-                    "publicKeyMultibase": config.public_service_key
-                }],
-                "service":[{
-                    "id":"#bsky_fg",
-                    "type":"BskyFeedGenerator",
-                    "serviceEndpoint":format!("https://{external_base}")
-                }]
-            }
+    pub async fn new(config: Config) -> Result<Self> {
+        let service_key = config.key.load()?;
+        let service_did = Did::new_owned(config.service_did())?;
+        let service_document = config.did_document(&service_key)?;
+
+        let http_client = reqwest::Client::builder().build()?;
+        let identity_resolver = CachingResolver::new(JacquardResolver::new(
+            http_client.clone(),
+            ResolverOptions::default(),
         ));
 
-        let service_did = ServiceDID(config.service_did);
-
-        let identity_resolver = Arc::new(SharedIdentityResolver(Arc::new(InnerIdentityResolver {
-            dns_resolver: Arc::new(dns_resolver),
-            http_client: http_client.clone(),
-            plc_hostname: config.plc_hostname,
-        })));
-
-        let web_context = Self(Arc::new(InnerWebContext {
-            http_client: http_client.clone(),
-            document_storage: Arc::new(LruDidDocumentStorage::new(NonZeroUsize::new(255).unwrap())),
-            key_provider: Arc::new(SimpleKeyProvider {
-                keys: signing_key_storage,
-            }),
-            service_document,
+        let pg = Arc::new(PgClient::new().await?);
+        let embedder = config.embedder.embedder()?;
+        pg.check_embedder(embedder.as_ref()).await?;
+        let recommender = Recommender::new(
+            BskyClient::new(&config.bsky).await?,
+            pg.clone(),
+            embedder,
+            CursorCodec::new(config.cursor_secret.as_bytes()),
+        );
+
+        let vip = VipAnnouncer::connect(&config.vip_topic).await;
+
+        Ok(Self(Arc::new(InnerWebContext {
+            feeds: FeedRegistry::from_config(&config)?,
+            config,
+            http_client,
             service_did,
+            service_document,
+            service_key,
             identity_resolver,
-        }));
-        Ok(web_context)
+            pg,
+            recommender,
+            vip,
+        })))
     }
 }
 
@@ -141,38 +130,98 @@ impl Deref for WebContext {
     }
 }
 
+impl ServiceAuth for WebContext {
+    type Resolver = CachingResolver<JacquardResolver>;
+
+    fn service_did(&self) -> &Did<'_> {
+        &self.0.service_did
+    }
+
+    fn resolver(&self) -> &Self::Resolver {
+        &self.0.identity_resolver
+    }
+
+    fn require_lxm(&self) -> bool {
+        true
+    }
+}
+
 impl FromRef<WebContext> for reqwest::Client {
     fn from_ref(context: &WebContext) -> Self {
         context.0.http_client.clone()
     }
 }
 
-impl FromRef<WebContext> for ServiceDocument {
+impl FromRef<WebContext> for Arc<PgClient> {
     fn from_ref(context: &WebContext) -> Self {
-        context.0.service_document.clone()
+        context.0.pg.clone()
     }
 }
 
-impl FromRef<WebContext> for ServiceDID {
+impl FromRef<WebContext> for DidDocument<'static> {
     fn from_ref(context: &WebContext) -> Self {
-        context.0.service_did.clone()
+        context.0.service_document.clone()
     }
 }
 
-impl FromRef<WebContext> for Arc<dyn DidDocumentStorage> {
-    fn from_ref(context: &WebContext) -> Self {
-        context.0.document_storage.clone()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jacquard_common::bytes::Bytes;
+    use rstest::rstest;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct CountingResolver {
+        options: ResolverOptions,
+        status: http::StatusCode,
+        calls: AtomicUsize,
     }
-}
 
-impl FromRef<WebContext> for Arc<dyn KeyProvider> {
-    fn from_ref(context: &WebContext) -> Self {
-        context.0.key_provider.clone()
+    impl CountingResolver {
+        fn new(status: http::StatusCode) -> Self {
+            Self {
+                options: ResolverOptions::default(),
+                status,
+                calls: AtomicUsize::new(0),
+            }
+        }
     }
-}
 
-impl FromRef<WebContext> for Arc<dyn IdentityResolver> {
-    fn from_ref(context: &WebContext) -> Self {
-        context.0.identity_resolver.clone()
+    impl IdentityResolver for CountingResolver {
+        fn options(&self) -> &ResolverOptions {
+            &self.options
+        }
+
+        async fn resolve_handle(
+            &self,
+            _handle: &Handle<'_>,
+        ) -> Result<Did<'static>, IdentityError> {
+            // Only DIDs are resolved here
+            Err(IdentityError::HttpStatus(http::StatusCode::NOT_FOUND))
+        }
+
+        async fn resolve_did_doc(&self, did: &Did<'_>) -> Result<DidDocResponse, IdentityError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(DidDocResponse {
+                buffer: Bytes::from(format!(r#"{{"id":"{}"}}"#, did.as_str())),
+                status: self.status,
+                requested: None,
+            })
+        }
+    }
+
+    #[rstest]
+    #[case(http::StatusCode::OK, 1)]
+    #[case(http::StatusCode::NOT_FOUND, 2)]
+    #[tokio::test]
+    async fn caches_resolved_documents(#[case] status: http::StatusCode, #[case] calls: usize) {
+        let resolver = CachingResolver::new(CountingResolver::new(status));
+        let did = Did::new("did:plc:klugggc44dmpomjkuzyahzjd").unwrap();
+
+        for _ in 0..2 {
+            let doc = resolver.resolve_did_doc(&did).await.unwrap();
+            assert_eq!(doc.status, status);
+        }
+        assert_eq!(resolver.inner.calls.load(Ordering::SeqCst), calls);
     }
 }